@@ -0,0 +1,386 @@
+use super::{decode::Decoder, BencodeError, BencodeErrorKind, DecodeOptions};
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, Visitor};
+
+pub fn from_bytes<'de, T: Deserialize<'de>>(input: &'de [u8]) -> Result<T, BencodeError> {
+    from_bytes_with_options(input, DecodeOptions::default())
+}
+
+pub fn from_bytes_with_options<'de, T: Deserialize<'de>>(
+    input: &'de [u8],
+    options: DecodeOptions,
+) -> Result<T, BencodeError> {
+    let mut deserializer = Deserializer {
+        decoder: Decoder::new(input, options)?,
+    };
+
+    // NOTE: errors raised by the `Deserialize` implementation itself, like a
+    // missing struct field, don't know where they happened. The best we can do
+    // is point at wherever the decoder stopped.
+    let value = T::deserialize(&mut deserializer).map_err(|err| match err.offset {
+        Some(_) => err,
+        None => BencodeError::new(err.kind, deserializer.decoder.position),
+    })?;
+
+    deserializer.decoder.finish()?;
+
+    Ok(value)
+}
+
+struct Deserializer<'de> {
+    decoder: Decoder<'de>,
+}
+
+impl<'de> Deserializer<'de> {
+    fn expect_end(&mut self) -> Result<(), BencodeError> {
+        match self.decoder.next()? {
+            b'e' => {
+                self.decoder.close_container();
+                Ok(())
+            }
+            c => {
+                self.decoder.position -= 1;
+                Err(self
+                    .decoder
+                    .error(BencodeErrorKind::UnexpectedByte(c as char)))
+            }
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = BencodeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BencodeError> {
+        match self.decoder.peek()? {
+            b'0'..=b'9' => visitor.visit_borrowed_bytes(self.decoder.decode_bytes()?),
+            b'i' => visitor.visit_i64(self.decoder.decode_int()?),
+            b'l' => {
+                self.decoder.open_container()?;
+                let value = visitor.visit_seq(ListAccess { de: self, count: 0 })?;
+                self.expect_end()?;
+                Ok(value)
+            }
+            b'd' => {
+                self.decoder.open_container()?;
+                let value = visitor.visit_map(DictAccess {
+                    de: self,
+                    previous_key: None,
+                    count: 0,
+                })?;
+                self.expect_end()?;
+                Ok(value)
+            }
+            c => Err(self
+                .decoder
+                .error(BencodeErrorKind::UnexpectedByte(c as char))),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BencodeError> {
+        match self.decoder.peek()? {
+            b'i' => visitor.visit_bool(self.decoder.decode_int()? != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BencodeError> {
+        match self.decoder.peek()? {
+            b'0'..=b'9' => {
+                let bytes = self.decoder.decode_bytes()?;
+
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => visitor.visit_borrowed_str(s),
+                    Err(_) => visitor.visit_borrowed_bytes(bytes),
+                }
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BencodeError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BencodeError> {
+        self.deserialize_str(visitor)
+    }
+
+    // NOTE: bencode has no null, so if we are asked for an option at all, the
+    // value is present. Missing dictionary entries become `None` through serde.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, BencodeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, BencodeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, BencodeError> {
+        match self.decoder.peek()? {
+            b'0'..=b'9' => {
+                let start = self.decoder.position;
+                let variant = std::str::from_utf8(self.decoder.decode_bytes()?).map_err(|_| {
+                    BencodeError::new(
+                        BencodeErrorKind::Message("enum variant is not valid UTF-8".to_string()),
+                        start,
+                    )
+                })?;
+
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            b'd' => {
+                self.decoder.open_container()?;
+                let value = visitor.visit_enum(VariantAccess { de: self })?;
+                self.expect_end()?;
+                Ok(value)
+            }
+            c => Err(self
+                .decoder
+                .error(BencodeErrorKind::UnexpectedByte(c as char))),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, BencodeError> {
+        self.decoder.parse_next_value()?;
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct
+    }
+}
+
+struct ListAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    count: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for ListAccess<'_, 'de> {
+    type Error = BencodeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, BencodeError> {
+        match self.de.decoder.peek()? {
+            b'e' => Ok(None),
+            _ => {
+                self.count += 1;
+                self.de.decoder.check_entry_count(self.count)?;
+                seed.deserialize(&mut *self.de).map(Some)
+            }
+        }
+    }
+}
+
+struct DictAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    previous_key: Option<&'de [u8]>,
+    count: usize,
+}
+
+impl<'de> de::MapAccess<'de> for DictAccess<'_, 'de> {
+    type Error = BencodeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, BencodeError> {
+        match self.de.decoder.peek()? {
+            b'e' => Ok(None),
+            b'0'..=b'9' => {
+                self.count += 1;
+                self.de.decoder.check_entry_count(self.count)?;
+
+                // Read the raw key first so we can check the ordering, then
+                // rewind and let the seed deserialize it for real.
+                let key_start = self.de.decoder.position;
+                let key = self.de.decoder.decode_bytes()?;
+                self.de
+                    .decoder
+                    .check_key_order(self.previous_key, key, key_start)?;
+                self.previous_key = Some(key);
+                self.de.decoder.position = key_start;
+
+                seed.deserialize(&mut *self.de).map(Some)
+            }
+            _ => Err(self.de.decoder.error(BencodeErrorKind::NonStringKey)),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, BencodeError> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+// NOTE: non-unit enum variants are encoded as a single entry dictionary,
+// where the key is the variant name, for example: "d7:Varianti1ee".
+struct VariantAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de> de::EnumAccess<'de> for VariantAccess<'_, 'de> {
+    type Error = BencodeError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), BencodeError> {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess<'_, 'de> {
+    type Error = BencodeError;
+
+    fn unit_variant(self) -> Result<(), BencodeError> {
+        Err(self.de.decoder.error(BencodeErrorKind::UnsupportedType(
+            "unit variant in dictionary",
+        )))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, BencodeError> {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, BencodeError> {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, BencodeError> {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::Limits;
+    use serde_derive::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Example<'a> {
+        name: &'a str,
+        #[serde(with = "serde_bytes")]
+        pieces: &'a [u8],
+        length: i64,
+        comment: Option<String>,
+        private: Option<bool>,
+    }
+
+    #[test]
+    fn test_from_bytes_struct() {
+        let mut input = b"d6:lengthi42e4:name6:sample6:pieces2:".to_vec();
+        input.extend_from_slice(&[0x00, 0xff]);
+        input.extend_from_slice(b"7:privatei1e7:unknownli1ei2eee");
+
+        let expected = Example {
+            name: "sample",
+            pieces: &[0x00, 0xff],
+            length: 42,
+            comment: None,
+            private: Some(true),
+        };
+
+        assert_eq!(Ok(expected), from_bytes(&input));
+    }
+
+    #[test]
+    fn test_from_bytes_map() {
+        let input = b"d3:fooi1e3:bari300ee";
+
+        let expected = HashMap::from([("foo".to_string(), 1_u16), ("bar".to_string(), 300_u16)]);
+
+        assert_eq!(Ok(expected), from_bytes(input));
+        assert!(from_bytes::<HashMap<String, u8>>(input).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_strict() {
+        let input = b"d3:fooi1e3:bari2ee";
+
+        assert!(from_bytes::<HashMap<String, i64>>(input).is_ok());
+        assert_eq!(
+            Err(BencodeError::new(
+                BencodeErrorKind::UnsortedKey("bar".to_string()),
+                9
+            )),
+            from_bytes_with_options::<HashMap<String, i64>>(input, DecodeOptions::strict())
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_limits() {
+        let input = b"d3:fooli1ei2ei3eee";
+
+        assert_eq!(
+            Err(BencodeError::new(BencodeErrorKind::TooDeep(1), 6)),
+            from_bytes_with_options::<HashMap<String, Vec<i64>>>(
+                input,
+                DecodeOptions {
+                    limits: Limits {
+                        max_depth: 1,
+                        ..Limits::default()
+                    },
+                    ..DecodeOptions::default()
+                }
+            )
+        );
+        assert_eq!(
+            Err(BencodeError::new(BencodeErrorKind::TooManyEntries(2), 13)),
+            from_bytes_with_options::<HashMap<String, Vec<i64>>>(
+                input,
+                DecodeOptions {
+                    limits: Limits {
+                        max_list_length: 2,
+                        ..Limits::default()
+                    },
+                    ..DecodeOptions::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_missing_field() {
+        let input = b"d6:lengthi42ee";
+
+        let actual = from_bytes::<Example>(input).unwrap_err();
+
+        assert_eq!(
+            BencodeErrorKind::Message("missing field `name`".to_string()),
+            actual.kind
+        );
+        assert_eq!(Some(input.len() - 1), actual.offset);
+    }
+}
@@ -0,0 +1,493 @@
+use super::{encode, BencodeError, BencodeErrorKind, Limits, Value, ValueRef, ValueRefKind};
+use std::cmp::Ordering;
+
+pub fn decode(encoded_value: &[u8]) -> Result<Value, BencodeError> {
+    decode_with_options(encoded_value, DecodeOptions::default())
+}
+
+pub fn decode_with_options(
+    encoded_value: &[u8],
+    options: DecodeOptions,
+) -> Result<Value, BencodeError> {
+    parse_with_options(encoded_value, options).map(|value| value.to_value())
+}
+
+pub fn parse(encoded_value: &[u8]) -> Result<ValueRef<'_>, BencodeError> {
+    parse_with_options(encoded_value, DecodeOptions::default())
+}
+
+pub fn parse_with_options(
+    encoded_value: &[u8],
+    options: DecodeOptions,
+) -> Result<ValueRef<'_>, BencodeError> {
+    let mut decoder = Decoder::new(encoded_value, options)?;
+    let value = decoder.parse_next_value()?;
+    decoder.finish()?;
+
+    Ok(value)
+}
+
+// NOTE: duplicate keys are resolved by keeping the last value, the same way
+// the lenient decoder does, so the output may contain less than the input.
+pub fn canonicalize(encoded_value: &[u8]) -> Result<Vec<u8>, BencodeError> {
+    decode(encoded_value).map(|value| encode(&value))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    // Reject anything that wouldn't survive a decode/encode round trip unchanged:
+    // unsorted or duplicate keys, leading zeros, "i-0e", and trailing data.
+    pub strict: bool,
+    pub limits: Limits,
+}
+
+impl DecodeOptions {
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn untrusted() -> Self {
+        Self {
+            limits: Limits::untrusted(),
+            ..Self::default()
+        }
+    }
+}
+
+pub(super) struct Decoder<'a> {
+    pub(super) input: &'a [u8],
+    pub(super) position: usize,
+    pub(super) options: DecodeOptions,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub(super) fn new(input: &'a [u8], options: DecodeOptions) -> Result<Self, BencodeError> {
+        let max_size = options.limits.max_size;
+
+        if input.len() > max_size {
+            return Err(BencodeError::new(
+                BencodeErrorKind::TooLarge(max_size),
+                max_size,
+            ));
+        }
+
+        Ok(Self {
+            input,
+            position: 0,
+            options,
+            depth: 0,
+        })
+    }
+
+    // Consumes the 'l' or 'd' that starts a list or dictionary.
+    pub(super) fn open_container(&mut self) -> Result<(), BencodeError> {
+        let max_depth = self.options.limits.max_depth;
+
+        if self.depth >= max_depth {
+            return Err(self.error(BencodeErrorKind::TooDeep(max_depth)));
+        }
+
+        self.depth += 1;
+        self.position += 1;
+        Ok(())
+    }
+
+    pub(super) fn close_container(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub(super) fn check_entry_count(&self, count: usize) -> Result<(), BencodeError> {
+        let max_list_length = self.options.limits.max_list_length;
+
+        if count > max_list_length {
+            return Err(self.error(BencodeErrorKind::TooManyEntries(max_list_length)));
+        }
+
+        Ok(())
+    }
+
+    // The lenient decoder ignores anything after the first value, since some
+    // callers (like the ut_metadata data message) have a payload tacked on.
+    pub(super) fn finish(&self) -> Result<(), BencodeError> {
+        if self.options.strict && self.position < self.input.len() {
+            return Err(self.error(BencodeErrorKind::TrailingData));
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn check_key_order(
+        &self,
+        previous_key: Option<&[u8]>,
+        key: &[u8],
+        key_start: usize,
+    ) -> Result<(), BencodeError> {
+        if !self.options.strict {
+            return Ok(());
+        }
+
+        match previous_key.map(|previous_key| previous_key.cmp(key)) {
+            Some(Ordering::Equal) => Err(BencodeError::new(
+                BencodeErrorKind::DuplicateKey(String::from_utf8_lossy(key).into_owned()),
+                key_start,
+            )),
+            Some(Ordering::Greater) => Err(BencodeError::new(
+                BencodeErrorKind::UnsortedKey(String::from_utf8_lossy(key).into_owned()),
+                key_start,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(super) fn error(&self, kind: BencodeErrorKind) -> BencodeError {
+        BencodeError::new(kind, self.position)
+    }
+
+    pub(super) fn peek(&self) -> Result<u8, BencodeError> {
+        self.input
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| self.error(BencodeErrorKind::UnexpectedEnd))
+    }
+
+    pub(super) fn next(&mut self) -> Result<u8, BencodeError> {
+        let byte = self.peek()?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub(super) fn parse_next_value(&mut self) -> Result<ValueRef<'a>, BencodeError> {
+        let start = self.position;
+
+        let kind = match self.peek()? {
+            b'0'..=b'9' => ValueRefKind::Bytes(self.decode_bytes()?),
+            b'i' => ValueRefKind::Int(self.decode_int()?),
+            b'l' => {
+                self.open_container()?;
+                let mut result = vec![];
+
+                while self.peek()? != b'e' {
+                    self.check_entry_count(result.len() + 1)?;
+                    result.push(self.parse_next_value()?);
+                }
+
+                // Consume the e
+                self.position += 1;
+                self.close_container();
+                ValueRefKind::List(result)
+            }
+            b'd' => {
+                self.open_container()?;
+                let mut result = vec![];
+                let mut previous_key = None;
+
+                while self.peek()? != b'e' {
+                    self.check_entry_count(result.len() + 1)?;
+
+                    let key_start = self.position;
+                    let key = match self.peek()? {
+                        b'0'..=b'9' => self.decode_bytes()?,
+                        _ => return Err(self.error(BencodeErrorKind::NonStringKey)),
+                    };
+
+                    self.check_key_order(previous_key, key, key_start)?;
+                    previous_key = Some(key);
+
+                    let value = self.parse_next_value()?;
+                    result.push((key, value));
+                }
+
+                // Consume the e
+                self.position += 1;
+                self.close_container();
+                ValueRefKind::Dict(result)
+            }
+            c => return Err(self.error(BencodeErrorKind::UnexpectedByte(c as char))),
+        };
+
+        Ok(ValueRef::new(
+            kind,
+            &self.input[start..self.position],
+            start,
+        ))
+    }
+
+    // NOTE: strings have the format "{length}:{content}", for example: "5:hello"
+    pub(super) fn decode_bytes(&mut self) -> Result<&'a [u8], BencodeError> {
+        let start = self.position;
+        let mut length = 0_usize;
+
+        loop {
+            match self.next()? {
+                c if c.is_ascii_digit() => {
+                    length = length
+                        .checked_mul(10)
+                        .and_then(|length| length.checked_add((c - b'0') as usize))
+                        .ok_or_else(|| {
+                            BencodeError::new(BencodeErrorKind::InvalidStringLength, start)
+                        })?;
+                }
+                b':' => break,
+                c => {
+                    self.position -= 1;
+                    return Err(self.error(BencodeErrorKind::UnexpectedByte(c as char)));
+                }
+            }
+        }
+
+        if self.options.strict && self.input[start] == b'0' && self.position - start > 2 {
+            return Err(BencodeError::new(BencodeErrorKind::LeadingZero, start));
+        }
+
+        let max_string_length = self.options.limits.max_string_length;
+
+        if length > max_string_length {
+            return Err(BencodeError::new(
+                BencodeErrorKind::StringTooLong(max_string_length),
+                start,
+            ));
+        }
+
+        let start = self.position;
+
+        match start.checked_add(length) {
+            Some(end) if end <= self.input.len() => {
+                self.position = end;
+                Ok(&self.input[start..end])
+            }
+            _ => Err(BencodeError::new(
+                BencodeErrorKind::UnexpectedEnd,
+                self.input.len(),
+            )),
+        }
+    }
+
+    // NOTE: integers always start with 'i' and end with 'e'.
+    // Negative integers have a '-' immediately after the 'i'.
+    pub(super) fn decode_int(&mut self) -> Result<i64, BencodeError> {
+        // Consume the i
+        self.position += 1;
+        let start = self.position;
+
+        // We only want to allow the `-` at the beginning of the integer.
+        if self.peek()? == b'-' {
+            self.position += 1;
+        }
+
+        loop {
+            match self.next()? {
+                c if c.is_ascii_digit() => {}
+                b'e' => break,
+                c => {
+                    self.position -= 1;
+                    return Err(self.error(BencodeErrorKind::UnexpectedByte(c as char)));
+                }
+            }
+        }
+
+        let digits = &self.input[start..self.position - 1];
+
+        if self.options.strict {
+            match digits {
+                [b'-', b'0'] => {
+                    return Err(BencodeError::new(BencodeErrorKind::NegativeZero, start))
+                }
+                [b'-', b'0', _, ..] | [b'0', _, ..] => {
+                    return Err(BencodeError::new(BencodeErrorKind::LeadingZero, start))
+                }
+                _ => {}
+            }
+        }
+
+        // NOTE: the slice only contains an optional '-' and ASCII digits, so it is
+        // always valid UTF-8. Parsing still fails for "ie", "i-e", and overflow.
+        std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| BencodeError::new(BencodeErrorKind::InvalidInteger, start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_decode_string() {
+        assert_eq!(decode("5:hello".as_bytes()), Ok(Value::from("hello")));
+        assert_eq!(decode("3:foo".as_bytes()), Ok(Value::from("foo")));
+        assert_eq!(decode("0:".as_bytes()), Ok(Value::from("")));
+    }
+
+    #[test]
+    fn test_decode_binary_string() {
+        let input = [b'4', b':', 0x00, 0xff, 0x80, b'a'];
+
+        assert_eq!(
+            decode(&input),
+            Ok(Value::Bytes(vec![0x00, 0xff, 0x80, b'a']))
+        );
+    }
+
+    #[test]
+    fn test_decode_integer() {
+        assert_eq!(decode("i5e".as_bytes()), Ok(Value::Int(5)));
+        assert_eq!(decode("i42e".as_bytes()), Ok(Value::Int(42)));
+        assert_eq!(decode("i-1e".as_bytes()), Ok(Value::Int(-1)));
+        assert_eq!(decode("i-1234e".as_bytes()), Ok(Value::Int(-1234)));
+    }
+
+    #[test]
+    fn test_decode_list() {
+        assert_eq!(
+            decode("l5:helloi52ee".as_bytes()),
+            Ok(Value::List(vec![Value::from("hello"), Value::Int(52)])),
+        );
+
+        assert_eq!(
+            decode("l3:fooe".as_bytes()),
+            Ok(Value::List(vec![Value::from("foo")])),
+        );
+
+        assert_eq!(decode("le".as_bytes()), Ok(Value::List(vec![])));
+    }
+
+    #[test]
+    fn test_decode_dictionary() {
+        let expected_map = BTreeMap::from([
+            (b"foo".to_vec(), Value::from("bar")),
+            (b"hello".to_vec(), Value::Int(52)),
+        ]);
+
+        assert_eq!(
+            decode("d3:foo3:bar5:helloi52ee".as_bytes()),
+            Ok(Value::Dict(expected_map))
+        );
+
+        assert_eq!(decode("de".as_bytes()), Ok(Value::Dict(BTreeMap::new())));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let cases: [(&[u8], BencodeErrorKind, usize); 9] = [
+            (b"", BencodeErrorKind::UnexpectedEnd, 0),
+            (b"5:hel", BencodeErrorKind::UnexpectedEnd, 5),
+            (b"l3:foo", BencodeErrorKind::UnexpectedEnd, 6),
+            (b"i12", BencodeErrorKind::UnexpectedEnd, 3),
+            (b"ie", BencodeErrorKind::InvalidInteger, 1),
+            (b"i-e", BencodeErrorKind::InvalidInteger, 1),
+            (b"i1x2e", BencodeErrorKind::UnexpectedByte('x'), 2),
+            (b"di1e3:fooe", BencodeErrorKind::NonStringKey, 1),
+            (b"x", BencodeErrorKind::UnexpectedByte('x'), 0),
+        ];
+
+        for (input, kind, offset) in cases {
+            assert_eq!(decode(input), Err(BencodeError::new(kind, offset)));
+        }
+    }
+
+    #[test]
+    fn test_decode_strict() {
+        let cases: [(&[u8], BencodeErrorKind, usize); 7] = [
+            (b"i03e", BencodeErrorKind::LeadingZero, 1),
+            (b"i-0e", BencodeErrorKind::NegativeZero, 1),
+            (b"i-01e", BencodeErrorKind::LeadingZero, 1),
+            (b"03:foo", BencodeErrorKind::LeadingZero, 0),
+            (
+                b"d3:fooi1e3:bari2ee",
+                BencodeErrorKind::UnsortedKey("bar".to_string()),
+                9,
+            ),
+            (
+                b"d3:fooi1e3:fooi2ee",
+                BencodeErrorKind::DuplicateKey("foo".to_string()),
+                9,
+            ),
+            (b"i1ei2e", BencodeErrorKind::TrailingData, 3),
+        ];
+
+        for (input, kind, offset) in cases {
+            assert_eq!(
+                decode_with_options(input, DecodeOptions::strict()),
+                Err(BencodeError::new(kind, offset))
+            );
+            assert!(decode(input).is_ok());
+        }
+
+        assert_eq!(
+            decode_with_options(b"d3:bari0e3:fooli-10e0:ee", DecodeOptions::strict()),
+            decode(b"d3:bari0e3:fooli-10e0:ee")
+        );
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        assert_eq!(
+            canonicalize(b"d3:fooi01e3:bar03:baze"),
+            Ok(b"d3:bar3:baz3:fooi1ee".to_vec())
+        );
+        assert_eq!(canonicalize(b"i-0e"), Ok(b"i0e".to_vec()));
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let limits = Limits {
+            max_depth: 2,
+            max_size: 32,
+            max_list_length: 3,
+            max_string_length: 5,
+        };
+        let options = DecodeOptions {
+            limits,
+            ..DecodeOptions::default()
+        };
+
+        let cases: [(&[u8], BencodeErrorKind, usize); 5] = [
+            (b"lllee", BencodeErrorKind::TooDeep(2), 2),
+            (b"li1ei2ei3ei4ee", BencodeErrorKind::TooManyEntries(3), 10),
+            (
+                b"d1:ai1e1:bi2e1:ci3e1:di4ee",
+                BencodeErrorKind::TooManyEntries(3),
+                19,
+            ),
+            (b"6:abcdef", BencodeErrorKind::StringTooLong(5), 0),
+            (&[b'l'; 33], BencodeErrorKind::TooLarge(32), 32),
+        ];
+
+        for (input, kind, offset) in cases {
+            assert_eq!(
+                decode_with_options(input, options),
+                Err(BencodeError::new(kind, offset))
+            );
+        }
+
+        assert!(decode_with_options(b"lli1ei2ei3ee5:abcdee", options).is_ok());
+    }
+
+    #[test]
+    fn test_decode_default_depth_limit() {
+        let input = [vec![b'l'; 10_000], vec![b'e'; 10_000]].concat();
+
+        assert_eq!(
+            decode(&input),
+            Err(BencodeError::new(BencodeErrorKind::TooDeep(256), 256))
+        );
+    }
+
+    #[test]
+    fn test_decode_overflow() {
+        assert_eq!(
+            decode("i99999999999999999999e".as_bytes()),
+            Err(BencodeError::new(BencodeErrorKind::InvalidInteger, 1))
+        );
+        assert_eq!(
+            decode("99999999999999999999999:".as_bytes()),
+            Err(BencodeError::new(BencodeErrorKind::InvalidStringLength, 0))
+        );
+    }
+}
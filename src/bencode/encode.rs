@@ -0,0 +1,109 @@
+use super::Value;
+
+pub fn encode(val: &Value) -> Vec<u8> {
+    let mut result = vec![];
+    encode_into(val, &mut result);
+    result
+}
+
+fn encode_into(val: &Value, result: &mut Vec<u8>) {
+    match val {
+        Value::Bytes(bytes) => {
+            result.extend_from_slice(bytes.len().to_string().as_bytes());
+            result.push(b':');
+            result.extend_from_slice(bytes);
+        }
+        Value::Int(n) => {
+            result.push(b'i');
+            result.extend_from_slice(n.to_string().as_bytes());
+            result.push(b'e');
+        }
+        Value::List(list) => {
+            result.push(b'l');
+            list.iter().for_each(|val| encode_into(val, result));
+            result.push(b'e');
+        }
+        Value::Dict(dict) => {
+            // NOTE: BTreeMap iterates in key order, which gives us the sorted
+            // keys that bencode requires for free.
+            result.push(b'd');
+            dict.iter().for_each(|(key, val)| {
+                result.extend_from_slice(key.len().to_string().as_bytes());
+                result.push(b':');
+                result.extend_from_slice(key);
+                encode_into(val, result);
+            });
+            result.push(b'e');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::decode;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_encode_string() {
+        assert_eq!(encode(&Value::from("hello")), "5:hello".as_bytes());
+        assert_eq!(encode(&Value::from("foo")), "3:foo".as_bytes());
+        assert_eq!(encode(&Value::from("")), "0:".as_bytes());
+        assert_eq!(encode(&Value::from("\x00")), vec![b'1', b':', b'\x00']);
+        assert_eq!(
+            encode(&Value::Bytes(vec![0xff, 0x80])),
+            vec![b'2', b':', 0xff, 0x80]
+        );
+    }
+
+    #[test]
+    fn test_encode_integer() {
+        assert_eq!(encode(&Value::Int(420)), "i420e".as_bytes());
+        assert_eq!(encode(&Value::Int(-69)), "i-69e".as_bytes());
+    }
+
+    #[test]
+    fn test_encode_list() {
+        assert_eq!("le".as_bytes(), encode(&Value::List(vec![])));
+
+        let input = vec![Value::Int(420), Value::from("hello")];
+
+        let expected = "li420e5:helloe".as_bytes();
+
+        assert_eq!(expected, encode(&Value::List(input)));
+    }
+
+    #[test]
+    fn test_encode_dictionary() {
+        let mut input = BTreeMap::new();
+
+        let expected = "de".as_bytes();
+        let actual = encode(&Value::Dict(input.clone()));
+        assert_eq!(expected, actual);
+
+        input.insert(b"foo".to_vec(), Value::from("bar"));
+        input.insert(b"baz".to_vec(), Value::Int(69));
+
+        let expected = "d3:bazi69e3:foo3:bare".as_bytes();
+        let actual = encode(&Value::Dict(input.clone()));
+        assert_eq!(expected, actual);
+
+        input.insert(b"a".to_vec(), Value::from("first"));
+        input.insert(b"z".to_vec(), Value::from("last"));
+
+        let expected = "d1:a5:first3:bazi69e3:foo3:bar1:z4:laste".as_bytes();
+        let actual = encode(&Value::Dict(input));
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut input = b"d4:infod6:lengthi420e6:pieces4:".to_vec();
+        input.extend_from_slice(&[0x00, 0xff, 0x13, 0x37]);
+        input.extend_from_slice(b"e4:listli-1e0:lee5:peers2:");
+        input.extend_from_slice(&[0xde, 0xad]);
+        input.push(b'e');
+
+        assert_eq!(input, encode(&decode(&input).unwrap()));
+    }
+}
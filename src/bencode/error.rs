@@ -0,0 +1,87 @@
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub struct BencodeError {
+    pub kind: BencodeErrorKind,
+    // NOTE: errors raised while serializing, or by a serde `Deserialize`
+    // implementation, don't point at a specific input byte.
+    pub offset: Option<usize>,
+}
+
+impl BencodeError {
+    pub fn new(kind: BencodeErrorKind, offset: usize) -> Self {
+        Self {
+            kind,
+            offset: Some(offset),
+        }
+    }
+
+    pub fn without_offset(kind: BencodeErrorKind) -> Self {
+        Self { kind, offset: None }
+    }
+}
+
+impl fmt::Display for BencodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{} at byte {}", self.kind, offset),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl serde::ser::Error for BencodeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::without_offset(BencodeErrorKind::Message(msg.to_string()))
+    }
+}
+
+impl serde::de::Error for BencodeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self::without_offset(BencodeErrorKind::Message(msg.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum BencodeErrorKind {
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+    #[error("unexpected byte {0:?}")]
+    UnexpectedByte(char),
+    #[error("invalid integer")]
+    InvalidInteger,
+    #[error("invalid string length")]
+    InvalidStringLength,
+    #[error("non-string dictionary key")]
+    NonStringKey,
+    #[error("integer or string length has a leading zero")]
+    LeadingZero,
+    #[error("negative zero")]
+    NegativeZero,
+    #[error("dictionary key {0:?} is out of order")]
+    UnsortedKey(String),
+    #[error("duplicate dictionary key {0:?}")]
+    DuplicateKey(String),
+    #[error("trailing data after value")]
+    TrailingData,
+    #[error("nested more than {0} levels deep")]
+    TooDeep(usize),
+    #[error("input is larger than {0} bytes")]
+    TooLarge(usize),
+    #[error("more than {0} list or dictionary entries")]
+    TooManyEntries(usize),
+    #[error("string is longer than {0} bytes")]
+    StringTooLong(usize),
+    #[error("cannot encode JSON {found} at {path}")]
+    UnsupportedJson { path: String, found: &'static str },
+    #[error("invalid {encoding} string at {path}")]
+    InvalidBinary {
+        path: String,
+        encoding: &'static str,
+    },
+    #[error("unsupported type: {0}")]
+    UnsupportedType(&'static str),
+    #[error("{0}")]
+    Message(String),
+}
@@ -0,0 +1,40 @@
+// NOTE: the default limits only guard against nesting deep enough to overflow
+// the stack. Anything coming from the network should use `Limits::untrusted`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub max_depth: usize,
+    pub max_size: usize,
+    pub max_list_length: usize,
+    pub max_string_length: usize,
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            max_size: usize::MAX,
+            max_list_length: usize::MAX,
+            max_string_length: usize::MAX,
+        }
+    }
+
+    // Big enough for the info dictionary of a very large torrent, where the
+    // `pieces` string alone can run to several megabytes.
+    pub fn untrusted() -> Self {
+        Self {
+            max_depth: 32,
+            max_size: 16 * 1024 * 1024,
+            max_list_length: 100_000,
+            max_string_length: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 256,
+            ..Self::unlimited()
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use super::{BencodeError, BencodeErrorKind, Value};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl BinaryEncoding {
+    pub fn key(&self) -> &'static str {
+        match self {
+            BinaryEncoding::Hex => "$hex",
+            BinaryEncoding::Base64 => "$base64",
+        }
+    }
+
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            BinaryEncoding::Hex => hex::encode(bytes),
+            BinaryEncoding::Base64 => BASE64.encode(bytes),
+        }
+    }
+
+    pub fn decode(&self, s: &str) -> Option<Vec<u8>> {
+        match self {
+            BinaryEncoding::Hex => hex::decode(s).ok(),
+            BinaryEncoding::Base64 => BASE64.decode(s).ok(),
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        [BinaryEncoding::Hex, BinaryEncoding::Base64]
+            .into_iter()
+            .find(|binary| binary.key() == key)
+    }
+}
+
+impl Value {
+    // NOTE: strings that are valid UTF-8 are written as plain JSON strings.
+    // Anything else is written as a single entry object, for example
+    // {"$hex": "00ff"}, so the bytes can be recovered exactly.
+    //
+    // Keys have to be JSON strings, so binary keys are written as "$hex:00ff"
+    // instead. Keys that really start with "$" get an extra one in front, so
+    // a dictionary like {"$hex": "00ff"} comes out as {"$$hex": "00ff"} and
+    // can't be mistaken for a byte string.
+    pub fn to_json_with(&self, binary: BinaryEncoding) -> serde_json::Value {
+        match self {
+            Value::Bytes(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => serde_json::Value::String(s.to_string()),
+                Err(_) => serde_json::json!({ binary.key(): binary.encode(bytes) }),
+            },
+            Value::Int(n) => serde_json::Value::Number((*n).into()),
+            Value::List(list) => {
+                serde_json::Value::Array(list.iter().map(|val| val.to_json_with(binary)).collect())
+            }
+            Value::Dict(dict) => serde_json::Value::Object(
+                dict.iter()
+                    .map(|(key, val)| (key_to_json(key, binary), val.to_json_with(binary)))
+                    .collect(),
+            ),
+        }
+    }
+
+    // The inverse of `to_json_with`, accepting either binary encoding.
+    // Only a single entry object whose key is exactly "$hex" or "$base64" is
+    // read as a byte string.
+    pub fn from_json(json: &serde_json::Value) -> Result<Self, BencodeError> {
+        from_json_at(json, "")
+    }
+
+    pub fn to_tree(&self, binary: BinaryEncoding) -> String {
+        let mut lines = vec![];
+        write_tree(self, binary, 0, None, &mut lines);
+        lines.join("\n")
+    }
+}
+
+fn from_json_at(json: &serde_json::Value, path: &str) -> Result<Value, BencodeError> {
+    let unsupported = |found| {
+        BencodeError::without_offset(BencodeErrorKind::UnsupportedJson {
+            path: display_path(path),
+            found,
+        })
+    };
+
+    match json {
+        serde_json::Value::String(s) => Ok(Value::from(s.as_str())),
+        serde_json::Value::Number(n) => {
+            n.as_i64().map(Value::Int).ok_or_else(|| match n.is_f64() {
+                true => unsupported("float"),
+                false => unsupported("integer larger than 64 bits"),
+            })
+        }
+        serde_json::Value::Array(list) => list
+            .iter()
+            .enumerate()
+            .map(|(i, val)| from_json_at(val, &join_path(path, &i.to_string())))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::List),
+        serde_json::Value::Object(object) => {
+            if let Some((key, serde_json::Value::String(s))) = object.iter().next() {
+                if let (1, Some(binary)) = (object.len(), BinaryEncoding::from_key(key)) {
+                    return binary.decode(s).map(Value::Bytes).ok_or_else(|| {
+                        BencodeError::without_offset(BencodeErrorKind::InvalidBinary {
+                            path: display_path(path),
+                            encoding: binary.key(),
+                        })
+                    });
+                }
+            }
+
+            object
+                .iter()
+                .map(|(key, val)| {
+                    let path = join_path(path, key);
+
+                    Ok((key_from_json(key, &path)?, from_json_at(val, &path)?))
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::Dict)
+        }
+        serde_json::Value::Bool(_) => Err(unsupported("boolean")),
+        serde_json::Value::Null => Err(unsupported("null")),
+    }
+}
+
+fn key_to_json(key: &[u8], binary: BinaryEncoding) -> String {
+    match std::str::from_utf8(key) {
+        Ok(key) if key.starts_with('$') => format!("${}", key),
+        Ok(key) => key.to_string(),
+        Err(_) => format!("{}:{}", binary.key(), binary.encode(key)),
+    }
+}
+
+fn key_from_json(key: &str, path: &str) -> Result<Vec<u8>, BencodeError> {
+    if let Some(escaped) = key.strip_prefix("$$") {
+        return Ok(format!("${}", escaped).into_bytes());
+    }
+
+    let binary = key
+        .split_once(':')
+        .and_then(|(prefix, encoded)| Some((BinaryEncoding::from_key(prefix)?, encoded)));
+
+    match binary {
+        Some((binary, encoded)) => binary.decode(encoded).ok_or_else(|| {
+            BencodeError::without_offset(BencodeErrorKind::InvalidBinary {
+                path: display_path(path),
+                encoding: binary.key(),
+            })
+        }),
+        None => Ok(key.as_bytes().to_vec()),
+    }
+}
+
+fn join_path(path: &str, segment: &str) -> String {
+    match path.is_empty() {
+        true => segment.to_string(),
+        false => format!("{}.{}", path, segment),
+    }
+}
+
+fn display_path(path: &str) -> String {
+    match path.is_empty() {
+        true => "<root>".to_string(),
+        false => path.to_string(),
+    }
+}
+
+fn write_tree(
+    value: &Value,
+    binary: BinaryEncoding,
+    depth: usize,
+    label: Option<String>,
+    lines: &mut Vec<String>,
+) {
+    let indent = "  ".repeat(depth);
+    let prefix = match &label {
+        Some(label) => format!("{}{}: ", indent, label),
+        None => indent.clone(),
+    };
+
+    // Nested values under a label are indented one more level than the label.
+    let child_depth = match label {
+        Some(_) => depth + 1,
+        None => depth,
+    };
+
+    match value {
+        Value::Bytes(bytes) => lines.push(format!("{}{}", prefix, format_bytes(bytes, binary))),
+        Value::Int(n) => lines.push(format!("{}{}", prefix, n)),
+        Value::List(list) if list.is_empty() => lines.push(format!("{}[]", prefix)),
+        Value::Dict(dict) if dict.is_empty() => lines.push(format!("{}{{}}", prefix)),
+        Value::List(list) => {
+            if label.is_some() {
+                lines.push(prefix.trim_end().to_string());
+            }
+
+            for (i, val) in list.iter().enumerate() {
+                write_tree(val, binary, child_depth, Some(i.to_string()), lines);
+            }
+        }
+        Value::Dict(dict) => {
+            if label.is_some() {
+                lines.push(prefix.trim_end().to_string());
+            }
+
+            for (key, val) in dict {
+                // Keys are only shown here, so there's nothing to escape.
+                let key = match std::str::from_utf8(key) {
+                    Ok(key) => key.to_string(),
+                    Err(_) => format!("{}:{}", binary.key(), binary.encode(key)),
+                };
+
+                write_tree(val, binary, child_depth, Some(key), lines);
+            }
+        }
+    }
+}
+
+fn format_bytes(bytes: &[u8], binary: BinaryEncoding) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => serde_json::Value::String(s.to_string()).to_string(),
+        Err(_) => format!("<{} bytes> {}", bytes.len(), binary.encode(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::{decode, encode};
+
+    #[test]
+    fn test_to_json_with_binary() {
+        let input = b"d4:name6:sample6:pieces2:\x00\xffe";
+        let value = decode(input).unwrap();
+
+        assert_eq!(
+            serde_json::json!({ "name": "sample", "pieces": { "$hex": "00ff" } }),
+            value.to_json_with(BinaryEncoding::Hex)
+        );
+        assert_eq!(
+            serde_json::json!({ "name": "sample", "pieces": { "$base64": "AP8=" } }),
+            value.to_json_with(BinaryEncoding::Base64)
+        );
+    }
+
+    #[test]
+    fn test_from_json() {
+        let input = serde_json::json!({
+            "name": "sample",
+            "length": -42,
+            "pieces": { "$hex": "00ff" },
+            "peers": { "$base64": "AP8=" },
+            "list": [1, "two", { "$hex": "" }],
+        });
+
+        let expected =
+            b"d6:lengthi-42e4:listli1e3:two0:e4:name6:sample5:peers2:\x00\xff6:pieces2:\x00\xffe";
+
+        assert_eq!(
+            Ok(expected.to_vec()),
+            Value::from_json(&input).map(|val| encode(&val))
+        );
+    }
+
+    #[test]
+    fn test_from_json_round_trip() {
+        let input = b"d4:name6:sample6:pieces2:\x00\xffe";
+        let value = decode(input).unwrap();
+
+        for binary in [BinaryEncoding::Hex, BinaryEncoding::Base64] {
+            assert_eq!(
+                Ok(value.clone()),
+                Value::from_json(&value.to_json_with(binary))
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_json_round_trip_with_binary_keys() {
+        // Like v2 `piece layers`, which are keyed by raw hashes.
+        let input = b"d2:\x00\xffi1e2:\x01\xffi2e4:$hex4:00ff4:name6:samplee";
+        let value = decode(input).unwrap();
+
+        assert_eq!(
+            serde_json::json!({
+                "$hex:00ff": 1,
+                "$hex:01ff": 2,
+                "$$hex": "00ff",
+                "name": "sample",
+            }),
+            value.to_json_with(BinaryEncoding::Hex)
+        );
+
+        for binary in [BinaryEncoding::Hex, BinaryEncoding::Base64] {
+            assert_eq!(
+                Ok(input.to_vec()),
+                Value::from_json(&value.to_json_with(binary)).map(|val| encode(&val))
+            );
+        }
+
+        assert_eq!(
+            "$hex:00ff: 1\n$hex:01ff: 2\n$hex: \"00ff\"\nname: \"sample\"",
+            value.to_tree(BinaryEncoding::Hex)
+        );
+    }
+
+    #[test]
+    fn test_from_json_errors() {
+        let cases = [
+            (serde_json::json!(1.5), "<root>", "float"),
+            (serde_json::json!({ "a": [true] }), "a.0", "boolean"),
+            (serde_json::json!({ "a": { "b": null } }), "a.b", "null"),
+            (
+                serde_json::json!(u64::MAX),
+                "<root>",
+                "integer larger than 64 bits",
+            ),
+        ];
+
+        for (input, path, found) in cases {
+            assert_eq!(
+                Err(BencodeError::without_offset(
+                    BencodeErrorKind::UnsupportedJson {
+                        path: path.to_string(),
+                        found,
+                    }
+                )),
+                Value::from_json(&input)
+            );
+        }
+
+        assert_eq!(
+            Err(BencodeError::without_offset(
+                BencodeErrorKind::InvalidBinary {
+                    path: "pieces".to_string(),
+                    encoding: "$hex",
+                }
+            )),
+            Value::from_json(&serde_json::json!({ "pieces": { "$hex": "zz" } }))
+        );
+
+        assert_eq!(
+            Err(BencodeError::without_offset(
+                BencodeErrorKind::InvalidBinary {
+                    path: "$base64:!".to_string(),
+                    encoding: "$base64",
+                }
+            )),
+            Value::from_json(&serde_json::json!({ "$base64:!": 1 }))
+        );
+    }
+
+    #[test]
+    fn test_to_tree() {
+        let input = b"d8:announce3:url4:infod5:filesld6:lengthi42eee6:pieces2:\x00\xffe4:listlee";
+        let value = decode(input).unwrap();
+
+        let expected = [
+            "announce: \"url\"",
+            "info:",
+            "  files:",
+            "    0:",
+            "      length: 42",
+            "  pieces: <2 bytes> 00ff",
+            "list: []",
+        ]
+        .join("\n");
+
+        assert_eq!(expected, value.to_tree(BinaryEncoding::Hex));
+    }
+}
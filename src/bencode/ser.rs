@@ -0,0 +1,457 @@
+use super::{BencodeError, BencodeErrorKind};
+use serde::ser::{self, Serialize};
+use std::fmt::Display;
+
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, BencodeError> {
+    let mut serializer = Serializer::default();
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+#[derive(Default)]
+struct Serializer {
+    output: Vec<u8>,
+}
+
+impl Serializer {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.output
+            .extend_from_slice(bytes.len().to_string().as_bytes());
+        self.output.push(b':');
+        self.output.extend_from_slice(bytes);
+    }
+
+    fn write_int(&mut self, n: impl Display) {
+        self.output.push(b'i');
+        self.output.extend_from_slice(n.to_string().as_bytes());
+        self.output.push(b'e');
+    }
+}
+
+fn unsupported(name: &'static str) -> BencodeError {
+    BencodeError::without_offset(BencodeErrorKind::UnsupportedType(name))
+}
+
+// NOTE: dictionary keys have to be strings, so rather than implementing a whole
+// second serializer just for keys, we serialize the key normally and then strip
+// the length prefix back off of the result.
+fn serialize_key<T: Serialize + ?Sized>(key: &T) -> Result<Vec<u8>, BencodeError> {
+    let encoded = to_bytes(key)?;
+
+    match encoded.iter().position(|b| *b == b':') {
+        Some(index) if encoded.first().is_some_and(u8::is_ascii_digit) => {
+            Ok(encoded[index + 1..].to_vec())
+        }
+        _ => Err(BencodeError::without_offset(BencodeErrorKind::NonStringKey)),
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = BencodeError;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = DictSerializer<'a>;
+    type SerializeStruct = DictSerializer<'a>;
+    type SerializeStructVariant = DictSerializer<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), BencodeError> {
+        self.write_int(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), BencodeError> {
+        self.write_int(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), BencodeError> {
+        Err(unsupported("f32"))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), BencodeError> {
+        Err(unsupported("f64"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), BencodeError> {
+        self.write_bytes(v.to_string().as_bytes());
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), BencodeError> {
+        self.write_bytes(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), BencodeError> {
+        self.write_bytes(v);
+        Ok(())
+    }
+
+    // NOTE: bencode has no null, so `None` is written as nothing at all.
+    // Dictionaries and lists skip any entry that serializes to nothing,
+    // which is how optional fields get left out.
+    fn serialize_none(self) -> Result<(), BencodeError> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), BencodeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), BencodeError> {
+        Err(unsupported("unit"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), BencodeError> {
+        Err(unsupported("unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), BencodeError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), BencodeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), BencodeError> {
+        self.output.push(b'd');
+        self.write_bytes(variant.as_bytes());
+        value.serialize(&mut *self)?;
+        self.output.push(b'e');
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, BencodeError> {
+        self.output.push(b'l');
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self, BencodeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self, BencodeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self, BencodeError> {
+        self.output.push(b'd');
+        self.write_bytes(variant.as_bytes());
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<DictSerializer<'a>, BencodeError> {
+        Ok(DictSerializer::new(self, false))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<DictSerializer<'a>, BencodeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<DictSerializer<'a>, BencodeError> {
+        self.output.push(b'd');
+        self.write_bytes(variant.as_bytes());
+        Ok(DictSerializer::new(self, true))
+    }
+}
+
+impl ser::SerializeSeq for &mut Serializer {
+    type Ok = ();
+    type Error = BencodeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), BencodeError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), BencodeError> {
+        self.output.push(b'e');
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut Serializer {
+    type Ok = ();
+    type Error = BencodeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), BencodeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), BencodeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Serializer {
+    type Ok = ();
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), BencodeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), BencodeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut Serializer {
+    type Ok = ();
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), BencodeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), BencodeError> {
+        // Close both the list and the dictionary wrapping the variant.
+        self.output.extend_from_slice(b"ee");
+        Ok(())
+    }
+}
+
+// NOTE: bencode requires dictionary keys to be sorted, but serde hands us
+// entries in whatever order the type iterates them. We hold on to every
+// encoded entry until the end, then sort them before writing them out.
+struct DictSerializer<'a> {
+    serializer: &'a mut Serializer,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    pending_key: Option<Vec<u8>>,
+    is_variant: bool,
+}
+
+impl<'a> DictSerializer<'a> {
+    fn new(serializer: &'a mut Serializer, is_variant: bool) -> Self {
+        Self {
+            serializer,
+            entries: vec![],
+            pending_key: None,
+            is_variant,
+        }
+    }
+
+    fn push_entry<T: Serialize + ?Sized>(
+        &mut self,
+        key: Vec<u8>,
+        value: &T,
+    ) -> Result<(), BencodeError> {
+        let value = to_bytes(value)?;
+
+        // `None` values encode to nothing, so we leave out the whole entry.
+        if !value.is_empty() {
+            self.entries.push((key, value));
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), BencodeError> {
+        self.entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let output = &mut self.serializer;
+        output.output.push(b'd');
+
+        for (key, value) in &self.entries {
+            output.write_bytes(key);
+            output.output.extend_from_slice(value);
+        }
+
+        output.output.push(b'e');
+
+        if self.is_variant {
+            output.output.push(b'e');
+        }
+
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for DictSerializer<'_> {
+    type Ok = ();
+    type Error = BencodeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), BencodeError> {
+        self.pending_key = Some(serialize_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), BencodeError> {
+        let key = self.pending_key.take().ok_or_else(|| {
+            BencodeError::without_offset(BencodeErrorKind::Message(
+                "serialize_value called before serialize_key".to_string(),
+            ))
+        })?;
+
+        self.push_entry(key, value)
+    }
+
+    fn end(self) -> Result<(), BencodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for DictSerializer<'_> {
+    type Ok = ();
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), BencodeError> {
+        self.push_entry(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<(), BencodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for DictSerializer<'_> {
+    type Ok = ();
+    type Error = BencodeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), BencodeError> {
+        self.push_entry(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<(), BencodeError> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::Serialize;
+    use std::collections::HashMap;
+
+    #[derive(Serialize)]
+    struct Example<'a> {
+        name: &'a str,
+        #[serde(with = "serde_bytes")]
+        pieces: &'a [u8],
+        length: i64,
+        comment: Option<String>,
+        #[serde(rename = "announce-list")]
+        announce_list: Vec<Vec<String>>,
+    }
+
+    #[test]
+    fn test_to_bytes_struct() {
+        let input = Example {
+            name: "sample",
+            pieces: &[0x00, 0xff],
+            length: 42,
+            comment: None,
+            announce_list: vec![vec!["a".to_string(), "b".to_string()]],
+        };
+
+        let mut expected =
+            b"d13:announce-listll1:a1:bee6:lengthi42e4:name6:sample6:pieces2:".to_vec();
+        expected.extend_from_slice(&[0x00, 0xff]);
+        expected.push(b'e');
+
+        assert_eq!(Ok(expected), to_bytes(&input));
+    }
+
+    #[test]
+    fn test_to_bytes_sorts_map_keys() {
+        let input = HashMap::from([("zebra", 1), ("apple", 2), ("mango", 3)]);
+
+        assert_eq!(
+            Ok(b"d5:applei2e5:mangoi3e5:zebrai1ee".to_vec()),
+            to_bytes(&input)
+        );
+    }
+
+    #[test]
+    fn test_to_bytes_unsupported() {
+        assert_eq!(
+            Err(BencodeError::without_offset(
+                BencodeErrorKind::UnsupportedType("f64")
+            )),
+            to_bytes(&1.5_f64)
+        );
+
+        assert_eq!(
+            Err(BencodeError::without_offset(BencodeErrorKind::NonStringKey)),
+            to_bytes(&HashMap::from([(1, 2)]))
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use serde::{
+    de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{Serialize, SerializeMap, Serializer},
+};
+use std::{collections::BTreeMap, fmt};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Bytes(Vec<u8>),
+    Int(i64),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&BTreeMap<Vec<u8>, Value>> {
+        match self {
+            Value::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_dict().and_then(|dict| dict.get(key.as_bytes()))
+    }
+
+    // Walks a dotted path like "info.files.0.length", where each segment is
+    // either a dictionary key or a list index.
+    pub fn select(&self, path: &str) -> Option<&Value> {
+        if path.is_empty() {
+            return Some(self);
+        }
+
+        path.split('.').try_fold(self, |val, segment| match val {
+            Value::Dict(dict) => dict.get(segment.as_bytes()),
+            Value::List(list) => segment.parse::<usize>().ok().and_then(|i| list.get(i)),
+            _ => None,
+        })
+    }
+
+    // NOTE: JSON has no binary string type, so any bytes that aren't valid
+    // UTF-8 are replaced. Use the bencode value directly if you need the bytes.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Bytes(bytes) => {
+                serde_json::Value::String(String::from_utf8_lossy(bytes).into_owned())
+            }
+            Value::Int(n) => serde_json::Value::Number((*n).into()),
+            Value::List(list) => {
+                serde_json::Value::Array(list.iter().map(Value::to_json).collect())
+            }
+            Value::Dict(dict) => serde_json::Value::Object(
+                dict.iter()
+                    .map(|(key, val)| (String::from_utf8_lossy(key).into_owned(), val.to_json()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(list: Vec<Value>) -> Self {
+        Value::List(list)
+    }
+}
+
+impl From<BTreeMap<Vec<u8>, Value>> for Value {
+    fn from(dict: BTreeMap<Vec<u8>, Value>) -> Self {
+        Value::Dict(dict)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            Value::Int(n) => serializer.serialize_i64(*n),
+            Value::List(list) => serializer.collect_seq(list),
+            Value::Dict(dict) => {
+                let mut map = serializer.serialize_map(Some(dict.len()))?;
+
+                for (key, val) in dict {
+                    map.serialize_entry(serde_bytes::Bytes::new(key), val)?;
+                }
+
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a bencode value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        i64::try_from(v)
+            .map(Value::Int)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut list = vec![];
+
+        while let Some(val) = seq.next_element()? {
+            list.push(val);
+        }
+
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut dict = BTreeMap::new();
+
+        while let Some((key, val)) = map.next_entry::<serde_bytes::ByteBuf, Value>()? {
+            dict.insert(key.into_vec(), val);
+        }
+
+        Ok(Value::Dict(dict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let input = Value::Dict(BTreeMap::from([
+            (b"foo".to_vec(), Value::from("bar")),
+            (
+                b"list".to_vec(),
+                Value::List(vec![Value::Int(-3), Value::from("baz")]),
+            ),
+        ]));
+
+        let expected = serde_json::json!({ "foo": "bar", "list": [-3, "baz"] });
+
+        assert_eq!(expected, input.to_json());
+    }
+
+    #[test]
+    fn test_accessors() {
+        let input = Value::Dict(BTreeMap::from([
+            (b"length".to_vec(), Value::Int(42)),
+            (b"name".to_vec(), Value::from("sample")),
+        ]));
+
+        assert_eq!(Some(42), input.get("length").and_then(Value::as_int));
+        assert_eq!(Some("sample"), input.get("name").and_then(Value::as_str));
+        assert_eq!(None, input.get("missing"));
+        assert_eq!(None, Value::Int(1).get("length"));
+    }
+
+    #[test]
+    fn test_select() {
+        let input = crate::bencode::decode(b"d4:infod5:filesld6:lengthi42eeeee").unwrap();
+
+        assert_eq!(Some(&input), input.select(""));
+        assert_eq!(Some(&Value::Int(42)), input.select("info.files.0.length"));
+        assert_eq!(None, input.select("info.files.1.length"));
+        assert_eq!(None, input.select("info.files.x"));
+        assert_eq!(None, input.select("info.missing"));
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut input = b"d4:infod6:lengthi420e6:pieces2:".to_vec();
+        input.extend_from_slice(&[0x00, 0xff]);
+        input.extend_from_slice(b"e4:listli-1e0:leee");
+
+        let value: Value = crate::bencode::from_bytes(&input).unwrap();
+
+        assert_eq!(crate::bencode::decode(&input), Ok(value.clone()));
+        assert_eq!(Ok(input), crate::bencode::to_bytes(&value));
+    }
+}
@@ -0,0 +1,135 @@
+use super::Value;
+use std::ops::Range;
+
+// NOTE: a `ValueRef` borrows from the input it was parsed from, and remembers
+// exactly which bytes it came from. This is what we want for anything that gets
+// hashed, like the info dictionary, since re-encoding a decoded value only gives
+// back the same bytes if the original encoding was canonical.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueRef<'a> {
+    pub kind: ValueRefKind<'a>,
+    raw: &'a [u8],
+    start: usize,
+}
+
+// Dictionary entries are kept in their original order, duplicates included,
+// so nothing about the input is lost.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValueRefKind<'a> {
+    Bytes(&'a [u8]),
+    Int(i64),
+    List(Vec<ValueRef<'a>>),
+    Dict(Vec<(&'a [u8], ValueRef<'a>)>),
+}
+
+impl<'a> ValueRef<'a> {
+    pub(super) fn new(kind: ValueRefKind<'a>, raw: &'a [u8], start: usize) -> Self {
+        Self { kind, raw, start }
+    }
+
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.start + self.raw.len()
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self.kind {
+            ValueRefKind::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self.kind {
+            ValueRefKind::Int(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[ValueRef<'a>]> {
+        match &self.kind {
+            ValueRefKind::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&[(&'a [u8], ValueRef<'a>)]> {
+        match &self.kind {
+            ValueRefKind::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    // If a key shows up more than once, the last one wins, which matches `decode`.
+    pub fn get(&self, key: &str) -> Option<&ValueRef<'a>> {
+        self.as_dict().and_then(|dict| {
+            dict.iter()
+                .rev()
+                .find(|(k, _)| *k == key.as_bytes())
+                .map(|(_, val)| val)
+        })
+    }
+
+    pub fn to_value(&self) -> Value {
+        match &self.kind {
+            ValueRefKind::Bytes(bytes) => Value::from(*bytes),
+            ValueRefKind::Int(n) => Value::Int(*n),
+            ValueRefKind::List(list) => Value::List(list.iter().map(ValueRef::to_value).collect()),
+            ValueRefKind::Dict(dict) => Value::Dict(
+                dict.iter()
+                    .map(|(key, val)| (key.to_vec(), val.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bencode::{encode, parse};
+
+    #[test]
+    fn test_parse_spans() {
+        let input = b"d8:announce3:url4:infod6:lengthi42e4:name3:fooee";
+
+        let root = parse(input).unwrap();
+        assert_eq!(0..input.len(), root.span());
+
+        let announce = root.get("announce").unwrap();
+        assert_eq!(Some("url"), announce.as_str());
+        assert_eq!(b"3:url", announce.raw());
+
+        let info = root.get("info").unwrap();
+        assert_eq!(b"d6:lengthi42e4:name3:fooe", info.raw());
+        assert_eq!(&input[info.span()], info.raw());
+        assert_eq!(Some(42), info.get("length").and_then(|len| len.as_int()));
+    }
+
+    #[test]
+    fn test_parse_keeps_non_canonical_bytes() {
+        let input = b"d4:name3:foo6:lengthi042ee";
+
+        let root = parse(input).unwrap();
+        let keys = root
+            .as_dict()
+            .unwrap()
+            .iter()
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![&b"name"[..], &b"length"[..]], keys);
+        assert_eq!(input, root.raw());
+        assert_eq!(
+            b"d6:lengthi42e4:name3:fooe".to_vec(),
+            encode(&root.to_value())
+        );
+    }
+}
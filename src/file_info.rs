@@ -1,16 +1,103 @@
 use crate::{Piece, Torrent};
 use anyhow::Result;
-use std::iter::Iterator;
-use tokio::fs::File;
+use std::{
+    collections::HashSet,
+    iter::Iterator,
+    path::{Path, PathBuf},
+};
+
+mod sanitize;
+use sanitize::number_component;
+pub use sanitize::sanitize_component;
+pub use sanitize::sanitize_path;
+pub use sanitize::PathChange;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileInfo {
-    path: String,
+    root: PathBuf,
+    files: Vec<OutputFile>,
+    piece_length: u64,
     pub pieces: Vec<Piece>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct OutputFile {
+    // Relative to the root, straight from the torrent, so not safe to use
+    // as is. Empty when the root is the file itself.
+    components: Vec<String>,
+    // Where this file starts within the torrent's data, as if every file
+    // were concatenated together in order.
+    offset: u64,
+    length: u64,
+    // BEP 47 padding is never written out, it only fills out pieces.
+    padding: bool,
+    executable: bool,
+    // Relative to the torrent's root, like `components`.
+    symlink: Option<Vec<String>>,
+}
+
+// A piece can span several files, so each piece maps onto one or more of these.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileSegment {
+    pub file_index: usize,
+    pub file_offset: u64,
+    pub piece_offset: usize,
+    pub length: usize,
+}
+
 impl FileInfo {
-    pub fn new(path: String, torrent: &Torrent) -> Self {
+    // NOTE: for single-file torrents, `path` is the file to write. For
+    // multi-file torrents, it's the directory that gets a folder named after
+    // the torrent, with every file inside of it.
+    //
+    // The torrent may have come from anywhere, so its sizes are checked here
+    // rather than trusted. See `validate_torrent` for a fuller check.
+    pub fn new(path: String, torrent: &Torrent) -> Result<Self> {
+        if torrent.piece_length <= 0 {
+            anyhow::bail!("Invalid piece length: {}", torrent.piece_length);
+        }
+
+        if torrent.length < 0 || torrent.files.iter().any(|file| file.length < 0) {
+            anyhow::bail!("Torrent has a negative length");
+        }
+
+        // NOTE: pieces are sliced across files by offset, so the files have to
+        // cover exactly `length`.
+        if !torrent.files.is_empty() {
+            let files_length = torrent
+                .files
+                .iter()
+                .try_fold(0_i64, |total, file| total.checked_add(file.length));
+
+            if files_length != Some(torrent.length) {
+                anyhow::bail!(
+                    "File lengths don't add up to the torrent's length of {}",
+                    torrent.length
+                );
+            }
+        }
+
+        // NOTE: downloads only check SHA-1 piece hashes. v2 pieces are per
+        // file and checked against merkle trees, and downloading them is out
+        // of scope, so hybrid torrents are downloaded as v1 ones.
+        if torrent.piece_hashes.is_empty() && torrent.hash_v2.is_some() && torrent.length > 0 {
+            anyhow::bail!("Downloading v2-only torrents isn't supported");
+        }
+
+        let expected_pieces = (torrent.length as u64).div_ceil(torrent.piece_length as u64);
+
+        if expected_pieces != torrent.piece_hashes.len() as u64 {
+            anyhow::bail!(
+                "Expected {} pieces, but the torrent has {}",
+                expected_pieces,
+                torrent.piece_hashes.len()
+            );
+        }
+
         // If our piece size divides evenly into the length, then the last piece
         // will be the same size as the others.
         // Otherwise, the last piece will only be the remaining size.
@@ -19,7 +106,7 @@ impl FileInfo {
             n => n as usize,
         };
 
-        let last_index = torrent.piece_hashes.len() - 1;
+        let last_index = torrent.piece_hashes.len().saturating_sub(1);
 
         let pieces = torrent
             .piece_hashes
@@ -35,7 +122,91 @@ impl FileInfo {
             })
             .collect();
 
-        Self { path, pieces }
+        let files = match torrent.files.is_empty() {
+            true => vec![OutputFile {
+                components: Vec::new(),
+                offset: 0,
+                length: torrent.length as u64,
+                padding: false,
+                executable: false,
+                symlink: None,
+            }],
+            false => {
+                let mut offset = 0;
+
+                torrent
+                    .files
+                    .iter()
+                    .map(|file| {
+                        let output_file = OutputFile {
+                            components: std::iter::once(&torrent.name)
+                                .chain(&file.path)
+                                .cloned()
+                                .collect(),
+                            offset,
+                            length: file.length as u64,
+                            padding: file.is_padding(),
+                            executable: file.is_executable(),
+                            symlink: match file.is_symlink() {
+                                true => file.symlink_path.clone(),
+                                false => None,
+                            },
+                        };
+
+                        offset += file.length as u64;
+                        output_file
+                    })
+                    .collect()
+            }
+        };
+
+        let mut file_info = Self {
+            root: PathBuf::from(path),
+            files,
+            piece_length: torrent.piece_length as u64,
+            pieces,
+        };
+
+        // Padding is all zeros, so there's no need to download it.
+        for piece_index in 0..file_info.pieces.len() {
+            for segment in file_info.file_segments(piece_index) {
+                if file_info.files[segment.file_index].padding {
+                    let range = segment.piece_offset..segment.piece_offset + segment.length;
+                    file_info.pieces[piece_index].fill_zeros(range);
+                }
+            }
+        }
+
+        Ok(file_info)
+    }
+
+    pub fn file_segments(&self, piece_index: usize) -> Vec<FileSegment> {
+        let piece_start = piece_index as u64 * self.piece_length;
+        let piece_end = piece_start + self.pieces[piece_index].len() as u64;
+
+        // NOTE: files are laid out back to back, so both their starts and ends
+        // are in order. Only the files overlapping the piece are looked at.
+        let first = self
+            .files
+            .partition_point(|file| file.offset + file.length <= piece_start);
+
+        self.files[first..]
+            .iter()
+            .take_while(|file| file.offset < piece_end)
+            .enumerate()
+            .filter_map(|(index, file)| {
+                let file_index = first + index;
+                let start = u64::max(piece_start, file.offset);
+                let end = u64::min(piece_end, file.offset + file.length);
+
+                (start < end).then(|| FileSegment {
+                    file_index,
+                    file_offset: start - file.offset,
+                    piece_offset: (start - piece_start) as usize,
+                    length: (end - start) as usize,
+                })
+            })
+            .collect()
     }
 
     pub fn is_complete(&self) -> bool {
@@ -46,7 +217,62 @@ impl FileInfo {
         self.pieces.iter().all(Piece::is_valid)
     }
 
-    pub async fn save_to_disk(&self) -> Result<()> {
+    // Where each file will be written, after making sure none of them can
+    // end up outside of the root, along with any paths that had to change.
+    //
+    // NOTE: different paths can sanitize to the same one, and some
+    // filesystems ignore case, so later files that clash with an earlier one
+    // get numbered. Otherwise both would be written to the same file.
+    pub fn output_paths(&self) -> (Vec<PathBuf>, Vec<PathChange>) {
+        let mut changes = Vec::new();
+        let mut seen = HashSet::new();
+
+        let paths = self
+            .files
+            .iter()
+            .map(|file| {
+                if file.components.is_empty() {
+                    return self.root.clone();
+                }
+
+                let (mut components, mut change) = sanitize_path(&file.components);
+
+                // Padding is never written, so it can't clash with anything.
+                if !file.padding && !seen.insert(components.join("/").to_lowercase()) {
+                    let last = components.len() - 1;
+                    let name = components[last].clone();
+
+                    for number in 1.. {
+                        components[last] = number_component(&name, number);
+
+                        if seen.insert(components.join("/").to_lowercase()) {
+                            break;
+                        }
+                    }
+
+                    let change = change.get_or_insert_with(|| PathChange {
+                        original: file.components.clone(),
+                        sanitized: Vec::new(),
+                        reasons: Vec::new(),
+                    });
+
+                    change.sanitized = components.clone();
+                    change.reasons.push("duplicate name");
+                }
+
+                changes.extend(change);
+
+                components
+                    .iter()
+                    .fold(self.root.clone(), |path, component| path.join(component))
+            })
+            .collect();
+
+        (paths, changes)
+    }
+
+    // Returns every path that had to be rewritten to be safe to write.
+    pub async fn save_to_disk(&self) -> Result<Vec<PathChange>> {
         if !self.is_complete() {
             anyhow::bail!("Not all file pieces are complete!");
         }
@@ -55,14 +281,474 @@ impl FileInfo {
             anyhow::bail!("Not all file pieces are valid!");
         }
 
-        let mut file = File::create(&self.path).await?;
+        let (paths, mut changes) = self.output_paths();
+
+        // Create every file up front, so that empty files still get written.
+        for (file, path) in self.files.iter().zip(&paths) {
+            if file.padding {
+                continue;
+            }
+
+            // A symlink replaces whatever is already there, but anything
+            // else would be written through it.
+            self.ensure_no_symlinks(path, file.symlink.is_none())
+                .await?;
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+
+            match &file.symlink {
+                Some(target) => {
+                    let (target, change) = self.symlink_target(&file.components, target);
+                    changes.extend(change);
+
+                    create_symlink(&target, path).await?;
+                }
+                None => {
+                    File::create(path).await?;
+                }
+            }
+        }
+
+        // Segments come out in order, so we only ever need one file open at a time.
+        let mut current_file: Option<(usize, File)> = None;
+
+        for (piece_index, piece) in self.pieces.iter().enumerate() {
+            for segment in self.file_segments(piece_index) {
+                let file = &self.files[segment.file_index];
+
+                if file.padding || file.symlink.is_some() {
+                    continue;
+                }
+
+                if !matches!(&current_file, Some((i, _)) if *i == segment.file_index) {
+                    // tokio only guarantees the data is written once we flush.
+                    if let Some((_, mut file)) = current_file.take() {
+                        file.flush().await?;
+                    }
+
+                    // A later entry may have put a symlink in its place.
+                    self.ensure_no_symlinks(&paths[segment.file_index], true)
+                        .await?;
+
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .open(&paths[segment.file_index])
+                        .await?;
+
+                    current_file = Some((segment.file_index, file));
+                }
+
+                let (_, file) = current_file.as_mut().unwrap();
+
+                let data = &piece.data()[segment.piece_offset..][..segment.length];
+
+                if let Err(err) = file.write_all(data).await {
+                    anyhow::bail!("Error writing file: {}", err);
+                }
+            }
+        }
+
+        if let Some((_, mut file)) = current_file {
+            file.flush().await?;
+        }
+
+        #[cfg(unix)]
+        for (file, path) in self.files.iter().zip(&paths) {
+            if file.executable && !file.padding && file.symlink.is_none() {
+                use std::os::unix::fs::PermissionsExt;
+
+                let mut permissions = fs::metadata(path).await?.permissions();
+                permissions.set_mode(permissions.mode() | 0o111);
+                fs::set_permissions(path, permissions).await?;
+            }
+        }
+
+        Ok(changes)
+    }
+
+    // NOTE: symlink targets are only safe relative to where the torrent says
+    // the link is. Once an earlier entry has made a directory a symlink,
+    // anything under it lands somewhere else, so we refuse to go through it.
+    async fn ensure_no_symlinks(&self, path: &Path, include_path: bool) -> Result<()> {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) => anyhow::bail!("{} is outside of the output path", path.display()),
+        };
+
+        let mut current = self.root.clone();
+        let mut components = relative.components().peekable();
+
+        while let Some(component) = components.next() {
+            current.push(component);
 
-        for piece in &self.pieces {
-            if let Err(err) = piece.write(&mut file).await {
-                anyhow::bail!("Error writing file: {}", err);
+            if components.peek().is_none() && !include_path {
+                break;
+            }
+
+            if let Ok(metadata) = fs::symlink_metadata(&current).await {
+                if metadata.file_type().is_symlink() {
+                    anyhow::bail!(
+                        "Refusing to write {} through the symlink at {}",
+                        path.display(),
+                        current.display()
+                    );
+                }
             }
         }
 
         Ok(())
     }
+
+    // NOTE: symlink targets are relative to the torrent's root, so we walk
+    // back up from the link's directory rather than pointing at an absolute
+    // path. The target is sanitized like any other path, so it can't point
+    // outside of the torrent either.
+    fn symlink_target(
+        &self,
+        components: &[String],
+        target: &[String],
+    ) -> (PathBuf, Option<PathChange>) {
+        let (target, change) = sanitize_path(target);
+
+        // Skip the torrent's own folder and the link itself.
+        let depth = components.len().saturating_sub(2);
+
+        let path = std::iter::repeat_n("..", depth)
+            .map(String::from)
+            .chain(target)
+            .collect();
+
+        (path, change)
+    }
+}
+
+#[cfg(unix)]
+async fn create_symlink(target: &Path, path: &Path) -> Result<()> {
+    // A previous download may have left one behind.
+    if fs::symlink_metadata(path).await.is_ok() {
+        fs::remove_file(path).await?;
+    }
+
+    Ok(fs::symlink(target, path).await?)
+}
+
+// NOTE: symlinks need extra privileges elsewhere, so we fall back to an
+// empty file in their place.
+#[cfg(not(unix))]
+async fn create_symlink(_target: &Path, path: &Path) -> Result<()> {
+    File::create(path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate_hash, TorrentFile};
+
+    fn multi_file_torrent(data: &[u8], piece_length: usize) -> Torrent {
+        Torrent {
+            length: data.len() as i64,
+            piece_length: piece_length as i64,
+            piece_hashes: data.chunks(piece_length).map(calculate_hash).collect(),
+            name: "sample".to_string(),
+            files: vec![
+                TorrentFile {
+                    length: 3,
+                    path: vec!["a.txt".to_string()],
+                    ..Default::default()
+                },
+                TorrentFile {
+                    length: 0,
+                    path: vec!["empty.txt".to_string()],
+                    ..Default::default()
+                },
+                TorrentFile {
+                    length: 7,
+                    path: vec!["nested".to_string(), "b.txt".to_string()],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_file_segments() {
+        let torrent = multi_file_torrent(b"0123456789", 4);
+        let file_info = FileInfo::new("/tmp".to_string(), &torrent).unwrap();
+
+        assert_eq!(
+            vec![
+                FileSegment {
+                    file_index: 0,
+                    file_offset: 0,
+                    piece_offset: 0,
+                    length: 3,
+                },
+                FileSegment {
+                    file_index: 2,
+                    file_offset: 0,
+                    piece_offset: 3,
+                    length: 1,
+                },
+            ],
+            file_info.file_segments(0)
+        );
+
+        assert_eq!(
+            vec![FileSegment {
+                file_index: 2,
+                file_offset: 5,
+                piece_offset: 0,
+                length: 2,
+            }],
+            file_info.file_segments(2)
+        );
+    }
+
+    #[test]
+    fn test_new_rejects_bad_sizes() {
+        let torrent = multi_file_torrent(b"0123456789", 4);
+
+        assert!(FileInfo::new("/tmp".to_string(), &Torrent::default()).is_err());
+        assert_eq!(
+            "Downloading v2-only torrents isn't supported",
+            FileInfo::new(
+                "/tmp".to_string(),
+                &Torrent {
+                    piece_hashes: Vec::new(),
+                    hash_v2: Some(crate::InfoHashV2([1; 32])),
+                    ..torrent.clone()
+                }
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        assert!(FileInfo::new(
+            "/tmp".to_string(),
+            &Torrent {
+                piece_hashes: Vec::new(),
+                ..torrent.clone()
+            }
+        )
+        .is_err());
+        assert!(FileInfo::new(
+            "/tmp".to_string(),
+            &Torrent {
+                length: -10,
+                ..torrent.clone()
+            }
+        )
+        .is_err());
+
+        // Still the right number of pieces, but the files only cover 9 bytes.
+        let mut short_files = torrent.clone();
+        short_files.files[0].length -= 1;
+
+        assert_eq!(
+            "File lengths don't add up to the torrent's length of 10",
+            FileInfo::new("/tmp".to_string(), &short_files)
+                .unwrap_err()
+                .to_string()
+        );
+
+        let mut huge_files = torrent;
+        huge_files.files[0].length = i64::MAX;
+
+        assert!(FileInfo::new("/tmp".to_string(), &huge_files).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_multi_file_to_disk() {
+        let data = b"0123456789";
+        let torrent = multi_file_torrent(data, 4);
+        let output_dir = tempfile::tempdir().unwrap();
+
+        let mut file_info =
+            FileInfo::new(output_dir.path().to_string_lossy().to_string(), &torrent).unwrap();
+
+        for (piece, chunk) in file_info.pieces.iter_mut().zip(data.chunks(4)) {
+            piece.update_block(0, chunk.to_vec());
+        }
+
+        file_info.save_to_disk().await.unwrap();
+
+        let root = output_dir.path().join("sample");
+        assert_eq!(b"012".to_vec(), std::fs::read(root.join("a.txt")).unwrap());
+        assert_eq!(
+            Vec::<u8>::new(),
+            std::fs::read(root.join("empty.txt")).unwrap()
+        );
+        assert_eq!(
+            b"3456789".to_vec(),
+            std::fs::read(root.join("nested").join("b.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_to_disk_sanitizes_paths() {
+        let data = b"0123456789";
+        let mut torrent = multi_file_torrent(data, 4);
+        torrent.name = "..".to_string();
+        torrent.files[2].path = vec!["..".to_string(), "/etc".to_string()];
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let root = output_dir.path().join("downloads");
+
+        let mut file_info = FileInfo::new(root.to_string_lossy().to_string(), &torrent).unwrap();
+
+        for (piece, chunk) in file_info.pieces.iter_mut().zip(data.chunks(4)) {
+            piece.update_block(0, chunk.to_vec());
+        }
+
+        let changes = file_info.save_to_disk().await.unwrap();
+
+        assert_eq!(3, changes.len());
+        assert_eq!(
+            vec!["_".to_string(), "_".to_string(), "_etc".to_string()],
+            changes[2].sanitized
+        );
+        assert_eq!(
+            b"3456789".to_vec(),
+            std::fs::read(root.join("_").join("_").join("_etc")).unwrap()
+        );
+        assert!(!output_dir.path().join("etc").exists());
+    }
+
+    #[tokio::test]
+    async fn test_save_to_disk_numbers_clashing_paths() {
+        let data = b"0123456789";
+        let mut torrent = multi_file_torrent(data, 4);
+        torrent.files[0].path = vec!["a".to_string(), "..".to_string()];
+        torrent.files[1].path = vec!["CON".to_string()];
+        torrent.files[2].path = vec!["A".to_string(), ".".to_string()];
+
+        let output_dir = tempfile::tempdir().unwrap();
+
+        let mut file_info =
+            FileInfo::new(output_dir.path().to_string_lossy().to_string(), &torrent).unwrap();
+
+        for (piece, chunk) in file_info.pieces.iter_mut().zip(data.chunks(4)) {
+            piece.update_block(0, chunk.to_vec());
+        }
+
+        let changes = file_info.save_to_disk().await.unwrap();
+
+        assert_eq!(3, changes.len());
+        assert_eq!(
+            vec!["directory traversal", "duplicate name"],
+            changes[2].reasons
+        );
+
+        let root = output_dir.path().join("sample");
+
+        assert_eq!(
+            b"012".to_vec(),
+            std::fs::read(root.join("a").join("_")).unwrap()
+        );
+        assert_eq!(
+            b"3456789".to_vec(),
+            std::fs::read(root.join("A").join("__1")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_to_disk_with_file_attributes() {
+        let data = b"abc\0\0\0\0\0defg";
+        let mut torrent = multi_file_torrent(data, 4);
+        torrent.files = vec![
+            TorrentFile {
+                length: 3,
+                path: vec!["run".to_string()],
+                attr: "x".to_string(),
+                ..Default::default()
+            },
+            TorrentFile {
+                length: 5,
+                path: vec![".pad".to_string(), "5".to_string()],
+                attr: "p".to_string(),
+                ..Default::default()
+            },
+            TorrentFile {
+                length: 4,
+                path: vec!["nested".to_string(), "b.txt".to_string()],
+                ..Default::default()
+            },
+            TorrentFile {
+                length: 0,
+                path: vec!["nested".to_string(), "link".to_string()],
+                attr: "l".to_string(),
+                symlink_path: Some(vec!["run".to_string()]),
+            },
+        ];
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let mut file_info =
+            FileInfo::new(output_dir.path().to_string_lossy().to_string(), &torrent).unwrap();
+
+        // The middle piece is nothing but padding.
+        assert!(file_info.pieces[1].is_complete());
+        assert!(!file_info.pieces[0].is_complete());
+
+        file_info.pieces[0].update_block(0, b"abc\0".to_vec());
+        file_info.pieces[2].update_block(0, b"defg".to_vec());
+
+        file_info.save_to_disk().await.unwrap();
+
+        let root = output_dir.path().join("sample");
+
+        assert!(!root.join(".pad").exists());
+        assert_eq!(b"abc".to_vec(), std::fs::read(root.join("run")).unwrap());
+        assert_eq!(
+            b"defg".to_vec(),
+            std::fs::read(root.join("nested").join("b.txt")).unwrap()
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(root.join("run"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(0o111, mode & 0o111);
+
+            let link = root.join("nested").join("link");
+            assert_eq!(PathBuf::from("../run"), std::fs::read_link(&link).unwrap());
+            assert_eq!(b"abc".to_vec(), std::fs::read(link).unwrap());
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_save_to_disk_refuses_nested_symlinks() {
+        let mut torrent = multi_file_torrent(b"", 4);
+        torrent.name = "t".to_string();
+        torrent.files = vec![
+            TorrentFile {
+                path: ["d1", "d2", "d3", "s"].map(String::from).to_vec(),
+                attr: "l".to_string(),
+                symlink_path: Some(vec!["t".to_string()]),
+                ..Default::default()
+            },
+            TorrentFile {
+                path: ["d1", "d2", "d3", "s", "esc"].map(String::from).to_vec(),
+                attr: "l".to_string(),
+                symlink_path: Some(vec!["outside".to_string()]),
+                ..Default::default()
+            },
+        ];
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let root = output_dir.path().join("dl");
+
+        let file_info = FileInfo::new(root.to_string_lossy().to_string(), &torrent).unwrap();
+
+        assert!(file_info.save_to_disk().await.is_err());
+        assert!(std::fs::symlink_metadata(root.join("t").join("esc")).is_err());
+        assert!(std::fs::symlink_metadata(root.join("t").join("t").join("esc")).is_err());
+    }
 }
@@ -0,0 +1,235 @@
+use std::fmt;
+
+// Most filesystems won't store a longer name than this, in bytes.
+const MAX_COMPONENT_LENGTH: usize = 255;
+
+// Windows refuses to create files with these names, with any extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// A file path that had to be rewritten before it was safe to write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathChange {
+    pub original: Vec<String>,
+    pub sanitized: Vec<String>,
+    pub reasons: Vec<&'static str>,
+}
+
+impl fmt::Display for PathChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} -> {:?} ({})",
+            self.original.join("/"),
+            self.sanitized.join("/"),
+            self.reasons.join(", ")
+        )
+    }
+}
+
+// Rewrites the components of a path from torrent metadata so that, joined
+// onto the output directory, it can only ever end up inside of it.
+pub fn sanitize_path(components: &[String]) -> (Vec<String>, Option<PathChange>) {
+    let mut reasons = Vec::new();
+
+    let mut sanitized = components
+        .iter()
+        .map(|component| {
+            let (component, component_reasons) = sanitize_component(component);
+
+            for reason in component_reasons {
+                add_reason(&mut reasons, reason);
+            }
+
+            component
+        })
+        .collect::<Vec<_>>();
+
+    if sanitized.is_empty() {
+        sanitized.push("_".to_string());
+        reasons.push("empty path");
+    }
+
+    let change = (!reasons.is_empty()).then(|| PathChange {
+        original: components.to_vec(),
+        sanitized: sanitized.clone(),
+        reasons,
+    });
+
+    (sanitized, change)
+}
+
+// Returns the safe version of a single path component, along with why it
+// had to change, if it did.
+pub fn sanitize_component(component: &str) -> (String, Vec<&'static str>) {
+    let mut reasons = Vec::new();
+
+    if component.is_empty() || component == "." || component == ".." {
+        return ("_".to_string(), vec!["directory traversal"]);
+    }
+
+    let mut sanitized = component
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => {
+                add_reason(&mut reasons, "path separator");
+                '_'
+            }
+            c if c.is_control() => {
+                add_reason(&mut reasons, "control character");
+                '_'
+            }
+            c => c,
+        })
+        .collect::<String>();
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+
+    if RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        sanitized.insert(0, '_');
+        reasons.push("reserved name");
+    }
+
+    if sanitized.len() > MAX_COMPONENT_LENGTH {
+        sanitized = truncate_component(&sanitized);
+        reasons.push("too long");
+    }
+
+    (sanitized, reasons)
+}
+
+// Renames a component that clashes with an earlier one, keeping its
+// extension, so "a.txt" becomes "a_1.txt".
+pub fn number_component(component: &str, number: usize) -> String {
+    let (stem, extension) = match component.rfind('.') {
+        Some(i) if i > 0 => component.split_at(i),
+        _ => (component, ""),
+    };
+
+    let suffix = format!("_{}{}", number, extension);
+    let mut end = usize::min(
+        stem.len(),
+        MAX_COMPONENT_LENGTH.saturating_sub(suffix.len()),
+    );
+
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}", &stem[..end], suffix)
+}
+
+// Each reason is only listed once, however many times it comes up.
+fn add_reason(reasons: &mut Vec<&'static str>, reason: &'static str) {
+    if !reasons.contains(&reason) {
+        reasons.push(reason);
+    }
+}
+
+// Cuts the name down to size, keeping a short extension if there is one.
+fn truncate_component(component: &str) -> String {
+    let extension = match component.rfind('.') {
+        Some(i) if component.len() - i <= 16 => &component[i..],
+        _ => "",
+    };
+
+    let mut end = MAX_COMPONENT_LENGTH - extension.len();
+
+    while !component.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}", &component[..end], extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components(path: &[&str]) -> Vec<String> {
+        path.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        let (sanitized, change) = sanitize_path(&components(&["..", "etc", "/passwd"]));
+
+        assert_eq!(components(&["_", "etc", "_passwd"]), sanitized);
+        assert_eq!(
+            Some(PathChange {
+                original: components(&["..", "etc", "/passwd"]),
+                sanitized: components(&["_", "etc", "_passwd"]),
+                reasons: vec!["directory traversal", "path separator"],
+            }),
+            change
+        );
+
+        assert_eq!(
+            (components(&["a", "b.txt"]), None),
+            sanitize_path(&components(&["a", "b.txt"]))
+        );
+        assert_eq!(components(&["_"]), sanitize_path(&[]).0);
+
+        assert_eq!(
+            vec!["path separator", "control character"],
+            sanitize_path(&components(&["a/b", "c\nd", "e:f"]))
+                .1
+                .unwrap()
+                .reasons
+        );
+    }
+
+    #[test]
+    fn test_sanitize_component() {
+        assert_eq!(
+            ("C__evil".to_string(), vec!["path separator"]),
+            sanitize_component("C:\\evil")
+        );
+        assert_eq!(
+            ("a_b".to_string(), vec!["control character"]),
+            sanitize_component("a\nb")
+        );
+        assert_eq!(
+            ("_con.txt".to_string(), vec!["reserved name"]),
+            sanitize_component("con.txt")
+        );
+        assert_eq!(
+            ("console.txt".to_string(), vec![]),
+            sanitize_component("console.txt")
+        );
+        assert_eq!(
+            (
+                "a_b_c_".to_string(),
+                vec!["path separator", "control character"]
+            ),
+            sanitize_component("a/b\nc/")
+        );
+    }
+
+    #[test]
+    fn test_number_component() {
+        assert_eq!("a_1.txt", number_component("a.txt", 1));
+        assert_eq!("_CON_2", number_component("_CON", 2));
+        assert_eq!(".hidden_1", number_component(".hidden", 1));
+
+        let numbered = number_component(&format!("{}.mkv", "é".repeat(125)), 10);
+        assert!(numbered.len() <= MAX_COMPONENT_LENGTH);
+        assert!(numbered.ends_with("_10.mkv"));
+    }
+
+    #[test]
+    fn test_sanitize_overlong_component() {
+        let long_name = format!("{}.mkv", "é".repeat(200));
+
+        let (sanitized, reasons) = sanitize_component(&long_name);
+
+        assert_eq!(vec!["too long"], reasons);
+        assert!(sanitized.len() <= MAX_COMPONENT_LENGTH);
+        assert!(sanitized.ends_with("é.mkv"));
+    }
+}
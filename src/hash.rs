@@ -1,7 +1,13 @@
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
 pub fn calculate_hash(input: &[u8]) -> String {
     let mut hasher = Sha1::new();
     hasher.update(input);
     format!("{:x}", hasher.finalize())
 }
+
+// BitTorrent v2 (BEP 52) uses SHA-256 instead.
+pub fn calculate_hash_v2(input: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(input))
+}
@@ -0,0 +1,126 @@
+use thiserror::Error;
+
+// RFC 4648, which is what magnet links use for base32 info hashes.
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ParseIdError {
+    #[error("expected {hex} hex or {base32} base32 characters, got {found}")]
+    InvalidLength {
+        hex: usize,
+        base32: usize,
+        found: usize,
+    },
+    #[error("invalid character {0:?}")]
+    InvalidCharacter(char),
+}
+
+// Accepts either hex or unpadded base32, telling them apart by length.
+pub(crate) fn decode_id<const N: usize>(input: &str) -> Result<[u8; N], ParseIdError> {
+    let base32_length = (N * 8).div_ceil(5);
+
+    if input.len() == N * 2 {
+        let mut output = [0; N];
+
+        const_hex::decode_to_slice(input, &mut output).map_err(|_| {
+            let c = input
+                .chars()
+                .find(|c| !c.is_ascii_hexdigit())
+                .unwrap_or('?');
+            ParseIdError::InvalidCharacter(c)
+        })?;
+
+        Ok(output)
+    } else if input.trim_end_matches('=').len() == base32_length {
+        let bytes = base32_decode(input.trim_end_matches('='))?;
+        Ok(bytes[..N].try_into().unwrap())
+    } else {
+        Err(ParseIdError::InvalidLength {
+            hex: N * 2,
+            base32: base32_length,
+            found: input.len(),
+        })
+    }
+}
+
+pub(crate) fn base32_encode(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer = 0_u32;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    output
+}
+
+fn base32_decode(input: &str) -> Result<Vec<u8>, ParseIdError> {
+    let mut output = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer = 0_u32;
+    let mut bits = 0;
+
+    for c in input.chars() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())
+            .ok_or(ParseIdError::InvalidCharacter(c))?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base32_round_trip() {
+        let bytes = *b"12345678901234567890";
+
+        assert_eq!("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", base32_encode(&bytes));
+        assert_eq!(
+            Ok(bytes),
+            decode_id::<20>("gezdgnbvgy3tqojqgezdgnbvgy3tqojq")
+        );
+        assert_eq!(Ok(bytes), decode_id::<20>(&hex::encode(bytes)));
+    }
+
+    #[test]
+    fn test_decode_id_errors() {
+        assert_eq!(
+            Err(ParseIdError::InvalidLength {
+                hex: 40,
+                base32: 32,
+                found: 3
+            }),
+            decode_id::<20>("abc")
+        );
+        assert_eq!(
+            Err(ParseIdError::InvalidCharacter('x')),
+            decode_id::<20>(&"x".repeat(40))
+        );
+        assert_eq!(
+            Err(ParseIdError::InvalidCharacter('1')),
+            decode_id::<20>(&"1".repeat(32))
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use crate::id_encoding::{base32_encode, decode_id, ParseIdError};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::{fmt, str::FromStr};
+
+// The SHA-1 hash of a torrent's info dictionary, which identifies it to
+// trackers and peers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InfoHash(pub [u8; 20]);
+
+// BEP 52 info hashes are SHA-256. Trackers and the peer handshake only have
+// room for 20 bytes, so they use the truncated form instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InfoHashV2(pub [u8; 32]);
+
+impl InfoHash {
+    pub fn of(info: &[u8]) -> Self {
+        Self(Sha1::digest(info).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0)
+    }
+}
+
+impl InfoHashV2 {
+    pub fn of(info: &[u8]) -> Self {
+        Self(Sha256::digest(info).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0)
+    }
+
+    pub fn truncated(&self) -> InfoHash {
+        InfoHash(self.0[..20].try_into().unwrap())
+    }
+}
+
+// Either hex or base32, as found in magnet links.
+impl FromStr for InfoHash {
+    type Err = ParseIdError;
+
+    fn from_str(hash: &str) -> Result<Self, ParseIdError> {
+        decode_id(hash).map(Self)
+    }
+}
+
+impl FromStr for InfoHashV2 {
+    type Err = ParseIdError;
+
+    fn from_str(hash: &str) -> Result<Self, ParseIdError> {
+        decode_id(hash).map(Self)
+    }
+}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", const_hex::encode(self.0))
+    }
+}
+
+impl fmt::Display for InfoHashV2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", const_hex::encode(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculate_hash;
+
+    #[test]
+    fn test_info_hash() {
+        let hash = InfoHash::of(b"d4:infoe");
+
+        assert_eq!(calculate_hash(b"d4:infoe"), hash.to_string());
+        assert_eq!(Ok(hash), hash.to_string().parse());
+        assert_eq!(Ok(hash), hash.to_base32().parse());
+        assert!("not a hash".parse::<InfoHash>().is_err());
+    }
+
+    #[test]
+    fn test_info_hash_v2() {
+        let hash = InfoHashV2::of(b"d4:infoe");
+
+        assert_eq!(64, hash.to_string().len());
+        assert_eq!(Ok(hash), hash.to_string().parse());
+        assert_eq!(Ok(hash), hash.to_base32().parse());
+        assert_eq!(&hash.to_string()[..40], hash.truncated().to_string());
+    }
+}
@@ -2,19 +2,52 @@ pub mod bencode;
 pub mod peers;
 
 mod torrent;
+pub use torrent::piece_layer;
+pub use torrent::pieces_root;
+pub use torrent::validate_torrent;
+pub use torrent::verify_piece_layer;
+pub use torrent::verify_piece_v2;
+pub use torrent::FileTreeEntry;
+pub use torrent::Info;
+pub use torrent::MerkleHash;
+pub use torrent::Metainfo;
+pub use torrent::Severity;
 pub use torrent::Torrent;
+pub use torrent::TorrentBuilder;
+pub use torrent::TorrentError;
+pub use torrent::TorrentFile;
+pub use torrent::TorrentVersion;
+pub use torrent::ValidationIssue;
+pub use torrent::ValidationReport;
 
 mod ip_address;
 pub use ip_address::IpAddress;
 
 mod file_info;
+pub use file_info::sanitize_component;
+pub use file_info::sanitize_path;
 pub use file_info::FileInfo;
+pub use file_info::FileSegment;
+pub use file_info::PathChange;
 
 mod piece;
 pub use piece::Piece;
 
 mod hash;
 pub use hash::calculate_hash;
+pub use hash::calculate_hash_v2;
+
+mod id_encoding;
+pub use id_encoding::ParseIdError;
+
+mod info_hash;
+pub use info_hash::InfoHash;
+pub use info_hash::InfoHashV2;
+
+mod peer_id;
+pub use peer_id::PeerId;
 
 mod magnet_link;
+pub use magnet_link::format_select_only;
+pub use magnet_link::parse_select_only;
 pub use magnet_link::MagnetLink;
@@ -1,11 +1,76 @@
+use crate::{InfoHash, InfoHashV2, Torrent};
 use anyhow::Result;
-use std::str::FromStr;
+use std::{fmt, ops::RangeInclusive, str::FromStr};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+// The multihash prefix for a 32 byte SHA-256 digest, which is all BEP 52
+// allows in `btmh`.
+const SHA256_MULTIHASH_PREFIX: &str = "1220";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct MagnetLink {
-    pub hash: String,
+    // NOTE: like `Torrent`, this is the truncated v2 hash for links that
+    // only have a `btmh`, since that's what trackers and peers expect.
+    pub hash: InfoHash,
+    pub hash_v2: Option<InfoHashV2>,
     pub file_name: String,
-    pub tracker_url: String,
+    pub trackers: Vec<String>,
+    // `x.pe` peers to try directly, as `host:port`.
+    pub peers: Vec<String>,
+    pub web_seeds: Vec<String>,
+    pub length: Option<u64>,
+    // BEP 53 file indices to download. Empty means every file.
+    pub select_only: Vec<RangeInclusive<usize>>,
+}
+
+impl MagnetLink {
+    // Only the hashes, name and length are filled in. Trackers and web seeds
+    // are left for the caller to add, since not every magnet should have them.
+    pub fn from_torrent(torrent: &Torrent) -> Self {
+        Self {
+            hash: torrent.hash,
+            hash_v2: torrent.hash_v2,
+            file_name: torrent.name.clone(),
+            // A malformed torrent may have a negative length, which isn't worth
+            // handing out.
+            length: u64::try_from(torrent.length).ok(),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_uri(&self) -> String {
+        self.to_string()
+    }
+
+    // Whether `hash` is a real v1 hash, rather than a truncated v2 one.
+    pub(crate) fn has_v1_hash(&self) -> bool {
+        self.hash_v2
+            .is_none_or(|hash_v2| hash_v2.truncated() != self.hash)
+    }
+
+    // The first tracker, or an empty string if there are none.
+    pub fn tracker_url(&self) -> &str {
+        self.trackers
+            .first()
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    // Magnet links don't have tiers, so each tracker gets its own and
+    // they're tried in the order they were given.
+    pub fn tracker_tiers(&self) -> Vec<Vec<String>> {
+        self.trackers
+            .iter()
+            .map(|tracker| vec![tracker.clone()])
+            .collect()
+    }
+
+    pub fn is_selected(&self, file_index: usize) -> bool {
+        self.select_only.is_empty()
+            || self
+                .select_only
+                .iter()
+                .any(|range| range.contains(&file_index))
+    }
 }
 
 impl FromStr for MagnetLink {
@@ -16,38 +81,136 @@ impl FromStr for MagnetLink {
             anyhow::bail!("Not a magnet link");
         }
 
-        let mut hash: Option<String> = None;
-        let mut file_name: Option<String> = None;
-        let mut tracker_url: Option<String> = None;
+        let mut magnet_link = Self::default();
+        let mut hash: Option<InfoHash> = None;
 
         let pairs = link[8..].split('&').flat_map(|s| s.split_once('='));
 
         for (key, value) in pairs {
+            // Keys can be repeated with a numbered suffix, like `tr.1`.
+            let key = match key.rsplit_once('.') {
+                Some((key, n)) if n.parse::<u32>().is_ok() => key,
+                _ => key,
+            };
+
             match key {
                 "xt" => {
-                    if !value.starts_with("urn:btih:") {
-                        anyhow::bail!("Invalid hash");
+                    let value = decode_value(value)?;
+
+                    if let Some(btih) = value.strip_prefix("urn:btih:") {
+                        // Either hex or base32, which is rejected here if invalid.
+                        hash = Some(btih.parse()?);
+                    } else if let Some(btmh) = value.strip_prefix("urn:btmh:") {
+                        magnet_link.hash_v2 = Some(parse_btmh(btmh)?);
                     }
-                    hash = Some(value[9..].to_owned());
                 }
-                "dn" => file_name = Some(value.to_owned()),
-                "tr" => tracker_url = Some(urlencoding::decode(value)?.into_owned()),
-                _ => anyhow::bail!("Invalid key: {}", key),
+                // Names are often form encoded, with `+` standing in for a space.
+                "dn" => magnet_link.file_name = decode_value(&value.replace('+', " "))?,
+                "tr" => magnet_link.trackers.push(decode_value(value)?),
+                "x.pe" => magnet_link.peers.push(decode_value(value)?),
+                "ws" => magnet_link.web_seeds.push(decode_value(value)?),
+                "xl" => match decode_value(value)?.parse() {
+                    Ok(length) => magnet_link.length = Some(length),
+                    Err(_) => anyhow::bail!("Invalid exact length: {}", value),
+                },
+                "so" => magnet_link.select_only = parse_select_only(&decode_value(value)?)?,
+                // NOTE: there are plenty of other keys out there, like `kt`
+                // or `as`, and none of them stop us from downloading. They're
+                // not decoded either, so a bad escape in one doesn't matter.
+                _ => {}
             }
         }
 
-        if hash.is_none() {
-            anyhow::bail!("No hash found");
+        magnet_link.hash = match (hash, magnet_link.hash_v2) {
+            (Some(hash), _) => hash,
+            (None, Some(hash_v2)) => hash_v2.truncated(),
+            (None, None) => anyhow::bail!("No hash found"),
+        };
+
+        Ok(magnet_link)
+    }
+}
+
+impl fmt::Display for MagnetLink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut params = Vec::new();
+
+        if self.has_v1_hash() {
+            params.push(format!("xt=urn:btih:{}", self.hash));
         }
 
-        Ok(Self {
-            hash: hash.unwrap(),
-            file_name: file_name.unwrap_or("".to_string()),
-            tracker_url: tracker_url.unwrap_or("".to_string()),
-        })
+        if let Some(hash_v2) = &self.hash_v2 {
+            params.push(format!(
+                "xt=urn:btmh:{}{}",
+                SHA256_MULTIHASH_PREFIX, hash_v2
+            ));
+        }
+
+        if !self.file_name.is_empty() {
+            params.push(format!("dn={}", urlencoding::encode(&self.file_name)));
+        }
+
+        if let Some(length) = self.length {
+            params.push(format!("xl={}", length));
+        }
+
+        for tracker in &self.trackers {
+            params.push(format!("tr={}", urlencoding::encode(tracker)));
+        }
+
+        for web_seed in &self.web_seeds {
+            params.push(format!("ws={}", urlencoding::encode(web_seed)));
+        }
+
+        for peer in &self.peers {
+            params.push(format!("x.pe={}", urlencoding::encode(peer)));
+        }
+
+        if !self.select_only.is_empty() {
+            params.push(format!("so={}", format_select_only(&self.select_only)));
+        }
+
+        write!(f, "magnet:?{}", params.join("&"))
+    }
+}
+
+fn decode_value(value: &str) -> Result<String> {
+    Ok(urlencoding::decode(value)?.into_owned())
+}
+
+fn parse_btmh(btmh: &str) -> Result<InfoHashV2> {
+    match btmh.strip_prefix(SHA256_MULTIHASH_PREFIX) {
+        Some(hash) if hash.len() == 64 => Ok(hash.parse()?),
+        _ => anyhow::bail!("Invalid v2 hash: {}", btmh),
     }
 }
 
+// A comma separated list of indices and inclusive ranges, like `0,2,4-6`.
+pub fn parse_select_only(value: &str) -> Result<Vec<RangeInclusive<usize>>> {
+    value
+        .split(',')
+        .map(|item| {
+            let (start, end) = item.split_once('-').unwrap_or((item, item));
+
+            match (start.parse(), end.parse()) {
+                (Ok(start), Ok(end)) if start <= end => Ok(start..=end),
+                _ => anyhow::bail!("Invalid select-only entry: {}", item),
+            }
+        })
+        .collect()
+}
+
+pub fn format_select_only(select_only: &[RangeInclusive<usize>]) -> String {
+    select_only
+        .iter()
+        .map(|range| match range.start() == range.end() {
+            true => range.start().to_string(),
+            false => format!("{}-{}", range.start(), range.end()),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,11 +223,151 @@ mod tests {
             tr=http%3A%2F%2Fbittorrent-test-tracker.codecrafters.io%2Fannounce";
 
         let expected = MagnetLink {
-            hash: "d69f91e6b2ae4c542468d1073a71d4ea13879a7f".to_string(),
+            hash: "d69f91e6b2ae4c542468d1073a71d4ea13879a7f".parse().unwrap(),
             file_name: "sample.torrent".to_string(),
-            tracker_url: "http://bittorrent-test-tracker.codecrafters.io/announce".to_string(),
+            trackers: vec!["http://bittorrent-test-tracker.codecrafters.io/announce".to_string()],
+            ..Default::default()
         };
 
         assert_eq!(expected, input.parse().unwrap());
     }
+
+    #[test]
+    fn test_from_str_with_every_key() {
+        let input = "magnet:?\
+            xt=urn:btih:22PZDZVSVZGFIJDI2EDTU4OU5IJYPGT7&\
+            xt=urn:btmh:1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e&\
+            dn=my%20file.txt&\
+            tr=http%3A%2F%2Fa%2Fannounce&\
+            tr.1=udp%3A%2F%2Fb%3A80&\
+            x.pe=10.0.0.1%3A6881&\
+            ws=http%3A%2F%2Fseed%2Ffile&\
+            xl=1337&\
+            so=0,2,4-6&\
+            kt=some+keywords%FF";
+
+        let link: MagnetLink = input.parse().unwrap();
+
+        assert_eq!(
+            "d69f91e6b2ae4c542468d1073a71d4ea13879a7f",
+            link.hash.to_string()
+        );
+        assert_eq!(
+            "caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e",
+            link.hash_v2.unwrap().to_string()
+        );
+        assert_eq!("my file.txt", link.file_name);
+        assert_eq!(vec!["http://a/announce", "udp://b:80"], link.trackers);
+        assert_eq!("http://a/announce", link.tracker_url());
+        assert_eq!(vec!["10.0.0.1:6881"], link.peers);
+        assert_eq!(vec!["http://seed/file"], link.web_seeds);
+        assert_eq!(Some(1337), link.length);
+        assert_eq!(vec![0..=0, 2..=2, 4..=6], link.select_only);
+        assert!(link.is_selected(5));
+        assert!(!link.is_selected(3));
+    }
+
+    #[test]
+    fn test_from_str_form_encoded_name() {
+        let input = "magnet:?xt=urn:btih:d69f91e6b2ae4c542468d1073a71d4ea13879a7f&\
+            dn=my+file%2B1.txt";
+
+        let link: MagnetLink = input.parse().unwrap();
+
+        assert_eq!("my file+1.txt", link.file_name);
+        assert!(
+            "magnet:?xt=urn:btih:d69f91e6b2ae4c542468d1073a71d4ea13879a7f&dn=%FF"
+                .parse::<MagnetLink>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_from_str_v2_only() {
+        let input = "magnet:?xt=urn:btmh:\
+            1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e";
+
+        let link: MagnetLink = input.parse().unwrap();
+
+        assert_eq!(link.hash_v2.unwrap().truncated(), link.hash);
+        assert_eq!("", link.tracker_url());
+        assert!(link.is_selected(42));
+    }
+
+    #[test]
+    fn test_to_uri() {
+        let link = MagnetLink {
+            hash: "d69f91e6b2ae4c542468d1073a71d4ea13879a7f".parse().unwrap(),
+            file_name: "a file & more.txt".to_string(),
+            trackers: vec!["http://tracker/announce?key=a b".to_string()],
+            web_seeds: vec!["http://seed/".to_string()],
+            length: Some(92063),
+            select_only: vec![0..=0, 2..=4],
+            ..Default::default()
+        };
+
+        let expected = "magnet:?\
+            xt=urn:btih:d69f91e6b2ae4c542468d1073a71d4ea13879a7f&\
+            dn=a%20file%20%26%20more.txt&\
+            xl=92063&\
+            tr=http%3A%2F%2Ftracker%2Fannounce%3Fkey%3Da%20b&\
+            ws=http%3A%2F%2Fseed%2F&\
+            so=0,2-4";
+
+        assert_eq!(expected, link.to_uri());
+        assert_eq!(link, expected.parse().unwrap());
+    }
+
+    #[test]
+    fn test_from_torrent() {
+        let hash_v2 = InfoHashV2([7; 32]);
+        let torrent = Torrent {
+            announce: "http://tracker".to_string(),
+            length: 100,
+            hash: hash_v2.truncated(),
+            hash_v2: Some(hash_v2),
+            name: "v2 only".to_string(),
+            ..Default::default()
+        };
+
+        let link = MagnetLink::from_torrent(&torrent);
+
+        // v2-only torrents have no btih to hand out.
+        assert_eq!(
+            format!(
+                "magnet:?xt=urn:btmh:1220{}&dn=v2%20only&xl=100",
+                const_hex::encode([7; 32])
+            ),
+            link.to_uri()
+        );
+        assert_eq!(link, link.to_uri().parse().unwrap());
+
+        let negative_length = Torrent {
+            length: -1,
+            ..torrent
+        };
+
+        assert_eq!(None, MagnetLink::from_torrent(&negative_length).length);
+    }
+
+    #[test]
+    fn test_from_str_rejects_invalid_hash() {
+        assert!("magnet:?xt=urn:btih:d69f91e6"
+            .parse::<MagnetLink>()
+            .is_err());
+        assert!("magnet:?xt=urn:btih:not-a-hash-not-a-hash-not-a-hash-nota"
+            .parse::<MagnetLink>()
+            .is_err());
+        assert!(
+            "magnet:?xt=urn:btmh:1114d69f91e6b2ae4c542468d1073a71d4ea13879a7f"
+                .parse::<MagnetLink>()
+                .is_err()
+        );
+        assert!("magnet:?dn=no-hash".parse::<MagnetLink>().is_err());
+        assert!(
+            "magnet:?xt=urn:btih:d69f91e6b2ae4c542468d1073a71d4ea13879a7f&so=3-1"
+                .parse::<MagnetLink>()
+                .is_err()
+        );
+    }
 }
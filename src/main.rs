@@ -1,14 +1,13 @@
+use anyhow::{Context, Result};
 use bittorrent_starter_rust::{
-    bencode, calculate_hash,
-    peers::{
-        self, generate_peer_id, ExtensionMessage, HandshakeReservedBytes, PeerMessage,
-        PeerMessageId,
-    },
-    FileInfo, MagnetLink, Torrent,
+    bencode::{self, BinaryEncoding},
+    format_select_only, parse_select_only,
+    peers::{self, generate_peer_id, HandshakeReservedBytes, PeerMessage, PeerMessageId},
+    sanitize_component, validate_torrent, FileInfo, InfoHash, MagnetLink, Metainfo, PeerId,
+    Torrent, TorrentBuilder, TorrentVersion,
 };
-use clap::{Parser, Subcommand};
-use serde_bencode::value::Value as BValue;
-use std::collections::HashMap;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::{Read, Write};
 use tokio::{fs::File, net::TcpStream};
 
 #[derive(Clone, Debug, Parser)]
@@ -23,11 +22,88 @@ struct Cli {
 #[clap(rename_all = "snake_case")]
 enum Commands {
     Decode {
-        encoded_value: String,
+        // Read from the file, or from stdin if neither this nor --file is given.
+        encoded_value: Option<String>,
+        #[arg(short, long, conflicts_with = "encoded_value")]
+        file: Option<String>,
+        #[arg(long, value_enum, default_value_t = DecodeFormat::Json)]
+        format: DecodeFormat,
+        #[arg(long, value_enum, default_value_t = BinaryFormat::Hex)]
+        binary: BinaryFormat,
+        // Select a single value, for example: info.files.0.length
+        #[arg(long)]
+        path: Option<String>,
+    },
+    Encode {
+        // Read from the file, or from stdin if neither this nor --file is given.
+        json: Option<String>,
+        #[arg(short, long, conflicts_with = "json")]
+        file: Option<String>,
+        #[arg(short, long = "out")]
+        output_path: Option<String>,
     },
     Info {
         file_path: String,
     },
+    Create {
+        // A single file, or a directory to add every file from.
+        path: String,
+        // Defaults to the name of the file or directory, plus ".torrent".
+        #[arg(short, long = "out")]
+        output_path: Option<String>,
+        // Picked based on the total size if not given.
+        #[arg(long)]
+        piece_length: Option<u64>,
+        // Each use adds a tier. Comma separate trackers that share a tier.
+        #[arg(short, long = "tracker")]
+        trackers: Vec<String>,
+        #[arg(short, long = "web_seed")]
+        web_seeds: Vec<String>,
+        #[arg(short, long)]
+        comment: Option<String>,
+        #[arg(long)]
+        private: bool,
+        #[arg(long)]
+        source: Option<String>,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long, value_enum, default_value_t = MetaVersion::V1)]
+        meta_version: MetaVersion,
+    },
+    Validate {
+        file_path: String,
+    },
+    Edit {
+        file_path: String,
+        // Defaults to overwriting the input file.
+        #[arg(short, long = "out")]
+        output_path: Option<String>,
+        // Each one is added in a tier of its own.
+        #[arg(long = "add_tracker")]
+        add_trackers: Vec<String>,
+        #[arg(long = "remove_tracker")]
+        remove_trackers: Vec<String>,
+        // Pass an empty comment to remove it.
+        #[arg(short, long)]
+        comment: Option<String>,
+        // Remove every tracker, leaving the torrent trackerless.
+        #[arg(long)]
+        strip_announce: bool,
+        // Replace FROM with TO in every tracker URL, e.g. to change a passkey.
+        #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+        replace_announce: Option<Vec<String>>,
+    },
+    Magnet {
+        file_path: String,
+        // Include every tracker from the torrent.
+        #[arg(long)]
+        trackers: bool,
+        #[arg(long)]
+        web_seeds: bool,
+        // BEP 53 file indices to download, e.g. 0,2,4-6.
+        #[arg(long)]
+        select_only: Option<String>,
+    },
     Peers {
         file_path: String,
     },
@@ -55,6 +131,12 @@ enum Commands {
     MagnetInfo {
         magnet_link: String,
     },
+    MagnetToTorrent {
+        magnet_link: String,
+        // Defaults to the torrent's name.
+        #[arg(short, long = "out")]
+        output_path: Option<String>,
+    },
     MagnetDownloadPiece {
         #[arg(short, long = "out")]
         output_path: Option<String>,
@@ -68,33 +150,375 @@ enum Commands {
     },
 }
 
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum DecodeFormat {
+    Json,
+    Tree,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum MetaVersion {
+    V1,
+    V2,
+    Hybrid,
+}
+
+impl From<MetaVersion> for TorrentVersion {
+    fn from(version: MetaVersion) -> Self {
+        match version {
+            MetaVersion::V1 => TorrentVersion::V1,
+            MetaVersion::V2 => TorrentVersion::V2,
+            MetaVersion::Hybrid => TorrentVersion::Hybrid,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum BinaryFormat {
+    Hex,
+    Base64,
+}
+
+impl From<BinaryFormat> for BinaryEncoding {
+    fn from(format: BinaryFormat) -> Self {
+        match format {
+            BinaryFormat::Hex => BinaryEncoding::Hex,
+            BinaryFormat::Base64 => BinaryEncoding::Base64,
+        }
+    }
+}
+
 // Usage: your_bittorrent.sh decode "<encoded_value>"
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Commands::Decode { encoded_value } => {
-            let decoded_value = bencode::decode(encoded_value.as_bytes());
-            println!("{}", decoded_value);
+        Commands::Decode {
+            encoded_value,
+            file,
+            format,
+            binary,
+            path,
+        } => {
+            let input = match (encoded_value, file) {
+                (Some(encoded_value), _) => Ok(encoded_value.as_bytes().to_vec()),
+                (None, Some(file)) => std::fs::read(file),
+                (None, None) => {
+                    let mut input = vec![];
+                    std::io::stdin().read_to_end(&mut input).map(|_| input)
+                }
+            };
+
+            let input = match input {
+                Ok(input) => input,
+                Err(err) => {
+                    eprintln!("Error reading input: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let decoded_value = match bencode::decode(&input) {
+                Ok(decoded_value) => decoded_value,
+                Err(err) => {
+                    eprintln!("Error decoding value: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let path = path.as_deref().unwrap_or("");
+            let selected_value = match decoded_value.select(path) {
+                Some(selected_value) => selected_value,
+                None => {
+                    eprintln!("Nothing found at path: {}", path);
+                    std::process::exit(1);
+                }
+            };
+
+            match format {
+                DecodeFormat::Json => {
+                    println!("{}", selected_value.to_json_with((*binary).into()))
+                }
+                DecodeFormat::Tree => println!("{}", selected_value.to_tree((*binary).into())),
+            }
+        }
+        Commands::Encode {
+            json,
+            file,
+            output_path,
+        } => {
+            let input = match (json, file) {
+                (Some(json), _) => Ok(json.clone()),
+                (None, Some(file)) => std::fs::read_to_string(file),
+                (None, None) => {
+                    let mut input = String::new();
+                    std::io::stdin().read_to_string(&mut input).map(|_| input)
+                }
+            };
+
+            let input = match input {
+                Ok(input) => input,
+                Err(err) => {
+                    eprintln!("Error reading input: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let json = match serde_json::from_str(&input) {
+                Ok(json) => json,
+                Err(err) => {
+                    eprintln!("Error parsing JSON: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let value = match bencode::Value::from_json(&json) {
+                Ok(value) => value,
+                Err(err) => {
+                    eprintln!("Error encoding value: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let encoded_value = bencode::encode(&value);
+
+            let result = match output_path {
+                Some(output_path) => std::fs::write(output_path, &encoded_value),
+                None => std::io::stdout().write_all(&encoded_value),
+            };
+
+            if let Err(err) = result {
+                eprintln!("Error writing output: {}", err);
+                std::process::exit(1);
+            }
         }
         Commands::Info { file_path } => {
-            let torrent = Torrent::from_file(file_path).unwrap();
-            println!("Tracker URL: {}", torrent.announce);
-            println!("Length: {}", torrent.length);
+            let metainfo = match Metainfo::from_file(file_path) {
+                Ok(metainfo) => metainfo,
+                Err(err) => {
+                    eprintln!("Error reading torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let info = &metainfo.info;
+
+            println!(
+                "Tracker URL: {}",
+                metainfo.tracker_url().unwrap_or_default()
+            );
+            println!("Length: {}", info.length);
+            match info.is_v1() {
+                true => println!("Info Hash: {}", info.hash),
+                false => println!(
+                    "Info Hash: {}",
+                    info.truncated_hash_v2().unwrap_or_default()
+                ),
+            }
+            if let Some(hash_v2) = &info.hash_v2 {
+                println!("Info Hash v2: {}", hash_v2);
+            }
+            println!("Piece Length: {}", info.piece_length);
+
+            // Only printed when the torrent actually has them.
+            if let Some(meta_version) = info.meta_version {
+                println!("Meta Version: {}", meta_version);
+            }
+            if let Some(name) = &info.name {
+                println!("Name: {}", name);
+            }
+            if let Some(comment) = &metainfo.comment {
+                println!("Comment: {}", comment);
+            }
+            if let Some(created_by) = &metainfo.created_by {
+                println!("Created By: {}", created_by);
+            }
+            if let Some(creation_date) = metainfo.creation_date {
+                println!("Creation Date: {}", creation_date);
+            }
+            if info.private {
+                println!("Private: yes");
+            }
+            if let Some(source) = &info.source {
+                println!("Source: {}", source);
+            }
+            if let Some(encoding) = &metainfo.encoding {
+                println!("Encoding: {}", encoding);
+            }
+
+            println!("Pieces: \n{}", info.piece_hashes.join("\n"));
+        }
+        Commands::Create {
+            path,
+            output_path,
+            piece_length,
+            trackers,
+            web_seeds,
+            comment,
+            private,
+            source,
+            name,
+            meta_version,
+        } => {
+            let mut builder = TorrentBuilder::new(path)
+                .created_by(concat!(
+                    env!("CARGO_PKG_NAME"),
+                    "/",
+                    env!("CARGO_PKG_VERSION")
+                ))
+                .private(*private)
+                .version((*meta_version).into());
+
+            for tier in trackers {
+                builder = builder.tracker_tier(tier.split(',').map(str::to_string).collect());
+            }
+
+            for web_seed in web_seeds {
+                builder = builder.web_seed(web_seed);
+            }
+
+            if let Some(piece_length) = piece_length {
+                builder = builder.piece_length(*piece_length);
+            }
+
+            if let Some(comment) = comment {
+                builder = builder.comment(comment);
+            }
+
+            if let Some(source) = source {
+                builder = builder.source(source);
+            }
+
+            if let Some(name) = name {
+                builder = builder.name(name);
+            }
+
+            let encoded_torrent = match builder.build() {
+                Ok(encoded_torrent) => encoded_torrent,
+                Err(err) => {
+                    eprintln!("Error creating torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let metainfo = match Metainfo::from_bytes(&encoded_torrent) {
+                Ok(metainfo) => metainfo,
+                Err(err) => {
+                    eprintln!("Error creating torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let output_path = output_path.clone().unwrap_or(format!(
+                "{}.torrent",
+                metainfo.info.name.clone().unwrap_or_default()
+            ));
+
+            if let Err(err) = std::fs::write(&output_path, &encoded_torrent) {
+                eprintln!("Error writing output: {}", err);
+                std::process::exit(1);
+            }
+
+            match metainfo.info.is_v1() {
+                true => println!("Info Hash: {}", metainfo.info.hash),
+                false => println!(
+                    "Info Hash: {}",
+                    metainfo.info.truncated_hash_v2().unwrap_or_default()
+                ),
+            }
+            if let Some(hash_v2) = &metainfo.info.hash_v2 {
+                println!("Info Hash v2: {}", hash_v2);
+            }
+        }
+        Commands::Validate { file_path } => {
+            let contents = match std::fs::read(file_path) {
+                Ok(contents) => contents,
+                Err(err) => {
+                    eprintln!("Error reading torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            let report = validate_torrent(&contents);
+
+            for issue in &report.issues {
+                println!("{}", issue);
+            }
+
+            if !report.is_valid() {
+                eprintln!("{} is not valid", file_path);
+                std::process::exit(1);
+            }
+
+            println!("{} is valid", file_path);
+        }
+        Commands::Edit {
+            file_path,
+            output_path,
+            add_trackers,
+            remove_trackers,
+            comment,
+            strip_announce,
+            replace_announce,
+        } => {
+            let mut torrent = match Torrent::from_file(file_path) {
+                Ok(torrent) => torrent,
+                Err(err) => {
+                    eprintln!("Error reading torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            // Stripping happens first, so it can be combined with adding new trackers.
+            if *strip_announce {
+                torrent.strip_trackers();
+            }
+
+            if let Some(replacement) = replace_announce {
+                torrent.replace_tracker_urls(&replacement[0], &replacement[1]);
+            }
+
+            for url in remove_trackers {
+                torrent.remove_tracker(url);
+            }
+
+            for url in add_trackers {
+                torrent.add_tracker(url);
+            }
+
+            if let Some(comment) = comment {
+                torrent.comment = (!comment.is_empty()).then(|| comment.clone());
+            }
+
+            let output_path = output_path.as_ref().unwrap_or(file_path);
+
+            if let Err(err) = std::fs::write(output_path, torrent.to_bytes()) {
+                eprintln!("Error writing output: {}", err);
+                std::process::exit(1);
+            }
+
             println!("Info Hash: {}", torrent.hash);
-            println!("Piece Length: {}", torrent.piece_length);
-            println!("Pieces: \n{}", torrent.piece_hashes.join("\n"));
         }
         Commands::Peers { file_path } => {
-            let torrent = Torrent::from_file(file_path).unwrap();
+            let torrent = match Torrent::from_file(file_path) {
+                Ok(torrent) => torrent,
+                Err(err) => {
+                    eprintln!("Error reading torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
             let peer_id = generate_peer_id();
             let torrent_peers = peers::fetch_peers(&torrent, &peer_id).unwrap();
 
             torrent_peers.iter().for_each(|peer| println!("{}", peer));
         }
         Commands::Handshake { file_path, peer_ip } => {
-            let torrent = Torrent::from_file(file_path).unwrap();
+            let torrent = match Torrent::from_file(file_path) {
+                Ok(torrent) => torrent,
+                Err(err) => {
+                    eprintln!("Error reading torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
             let peer_id = generate_peer_id();
 
             let mut stream = match TcpStream::connect(peer_ip).await {
@@ -113,7 +537,7 @@ async fn main() {
             )
             .await
             {
-                Ok(result) => println!("Peer ID: {}", result.encoded_peer_id),
+                Ok(result) => println!("Peer ID: {}", result.peer_id),
                 Err(err) => {
                     eprintln!("Error shaking hands: {}", err);
                     std::process::exit(1);
@@ -125,7 +549,13 @@ async fn main() {
             file_path,
             piece_index,
         } => {
-            let torrent = Torrent::from_file(file_path).unwrap();
+            let torrent = match Torrent::from_file(file_path) {
+                Ok(torrent) => torrent,
+                Err(err) => {
+                    eprintln!("Error reading torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
             let peer_id = generate_peer_id();
 
             if *piece_index >= torrent.piece_hashes.len() {
@@ -134,12 +564,18 @@ async fn main() {
             }
 
             let torrent_peers = peers::fetch_peers(&torrent, &peer_id).unwrap();
-            let peer_ip = torrent_peers[0];
+            let peer = torrent_peers[0];
 
             let output_path = output_path.clone().unwrap_or("/tmp/output".to_string());
-            let mut file_info = FileInfo::new(output_path.clone(), &torrent);
+            let mut file_info = match FileInfo::new(output_path.clone(), &torrent) {
+                Ok(file_info) => file_info,
+                Err(err) => {
+                    eprintln!("Invalid torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
 
-            let mut stream = match TcpStream::connect(peer_ip).await {
+            let mut stream = match TcpStream::connect(peer.address).await {
                 Ok(stream) => stream,
                 Err(err) => {
                     eprintln!("Error connecting to peer: {}", err);
@@ -147,9 +583,10 @@ async fn main() {
                 }
             };
 
-            if let Err(err) = peers::shake_hands(
+            if let Err(err) = peers::shake_hands_in_swarm(
                 &mut stream,
                 &torrent,
+                &peer.hash,
                 &peer_id,
                 HandshakeReservedBytes::empty(),
             )
@@ -203,16 +640,28 @@ async fn main() {
             output_path,
             file_path,
         } => {
-            let torrent = Torrent::from_file(file_path).unwrap();
+            let torrent = match Torrent::from_file(file_path) {
+                Ok(torrent) => torrent,
+                Err(err) => {
+                    eprintln!("Error reading torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
             let peer_id = generate_peer_id();
 
             let torrent_peers = peers::fetch_peers(&torrent, &peer_id).unwrap();
-            let peer_ip = torrent_peers[0];
+            let peer = torrent_peers[0];
 
             let output_path = output_path.clone().unwrap_or("/tmp/output".to_string());
-            let mut file_info = FileInfo::new(output_path.clone(), &torrent);
+            let mut file_info = match FileInfo::new(output_path.clone(), &torrent) {
+                Ok(file_info) => file_info,
+                Err(err) => {
+                    eprintln!("Invalid torrent: {}", err);
+                    std::process::exit(1);
+                }
+            };
 
-            let mut stream = match TcpStream::connect(peer_ip).await {
+            let mut stream = match TcpStream::connect(peer.address).await {
                 Ok(stream) => stream,
                 Err(err) => {
                     eprintln!("Error connecting to peer: {}", err);
@@ -220,9 +669,10 @@ async fn main() {
                 }
             };
 
-            if let Err(err) = peers::shake_hands(
+            if let Err(err) = peers::shake_hands_in_swarm(
                 &mut stream,
                 &torrent,
+                &peer.hash,
                 &peer_id,
                 HandshakeReservedBytes::empty(),
             )
@@ -253,228 +703,151 @@ async fn main() {
                 }
             }
 
-            if let Err(err) = file_info.save_to_disk().await {
-                eprintln!("Unable to save file to disk: {}", err);
-                std::process::exit(1);
+            match file_info.save_to_disk().await {
+                Ok(changes) => {
+                    for change in changes {
+                        eprintln!("Renamed {}", change);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Unable to save file to disk: {}", err);
+                    std::process::exit(1);
+                }
             }
         }
-        Commands::MagnetParse { magnet_link } => {
-            let magnet_link: MagnetLink = magnet_link.parse().unwrap();
-
-            println!("Tracker URL: {}", magnet_link.tracker_url);
-            println!("Info Hash: {}", magnet_link.hash);
-        }
-        Commands::MagnetHandshake { magnet_link } => {
-            let magnet_link: MagnetLink = magnet_link.parse().unwrap();
-            let placeholder_torrent = Torrent {
-                hash: magnet_link.hash,
-                announce: magnet_link.tracker_url,
-                length: 999, // fake length to make the peer happy
-                ..Default::default()
-            };
-
-            let peer_id = generate_peer_id();
-            let peers = peers::fetch_peers(&placeholder_torrent, &peer_id).unwrap();
-            let peer_ip = peers[0];
-
-            let mut stream = match TcpStream::connect(peer_ip).await {
-                Ok(stream) => stream,
+        Commands::Magnet {
+            file_path,
+            trackers,
+            web_seeds,
+            select_only,
+        } => {
+            let torrent = match Torrent::from_file(file_path) {
+                Ok(torrent) => torrent,
                 Err(err) => {
-                    eprintln!("Error connecting to peer: {}", err);
+                    eprintln!("Error reading torrent: {}", err);
                     std::process::exit(1);
                 }
             };
 
-            let base_handshake_result = match peers::shake_hands(
-                &mut stream,
-                &placeholder_torrent,
-                &peer_id,
-                HandshakeReservedBytes::ExtensionsEnabled,
-            )
-            .await
-            {
-                Ok(base_handshake_result) => base_handshake_result,
-                Err(err) => {
-                    eprintln!("Error shaking hands: {}", err);
-                    std::process::exit(1);
+            let mut magnet_link = MagnetLink::from_torrent(&torrent);
+
+            if *trackers {
+                for tracker in torrent.tracker_tiers().into_iter().flatten() {
+                    if !magnet_link.trackers.contains(&tracker) {
+                        magnet_link.trackers.push(tracker);
+                    }
                 }
-            };
+            }
 
-            println!("Peer ID: {}", base_handshake_result.encoded_peer_id);
+            if *web_seeds {
+                magnet_link.web_seeds = torrent.web_seeds();
+            }
 
-            // Recieve the bitfield message
-            match PeerMessage::read(&mut stream).await {
-                Ok(message) => match message.id {
-                    PeerMessageId::Bitfield => {}
-                    _ => {
-                        eprintln!("Unexpected message: {:?}", message);
+            if let Some(select_only) = select_only {
+                magnet_link.select_only = match parse_select_only(select_only) {
+                    Ok(select_only) => select_only,
+                    Err(err) => {
+                        eprintln!("{}", err);
                         std::process::exit(1);
                     }
-                },
-                Err(err) => {
-                    eprintln!("Error reading bitfield message: {}", err);
-                    std::process::exit(1);
-                }
+                };
             }
 
-            if !base_handshake_result
-                .reserved_bytes
-                .contains(HandshakeReservedBytes::ExtensionsEnabled)
-            {
-                return;
+            println!("{}", magnet_link);
+        }
+        Commands::MagnetParse { magnet_link } => {
+            let magnet_link = parse_magnet_link(magnet_link);
+
+            println!("Tracker URL: {}", magnet_link.tracker_url());
+            println!("Info Hash: {}", magnet_link.hash);
+
+            if let Some(hash_v2) = magnet_link.hash_v2 {
+                println!("Info Hash v2: {}", hash_v2);
             }
 
-            let extensions = match peers::shake_hands_extension(&mut stream).await {
-                Ok(extensions) => extensions,
-                Err(err) => {
-                    eprintln!("Error shaking hands for extensions: {}", err);
-                    std::process::exit(1);
-                }
-            };
+            if !magnet_link.file_name.is_empty() {
+                println!("Name: {}", magnet_link.file_name);
+            }
 
-            if let Some(ut_metadata) = extensions.ut_metadata {
-                println!("Peer Metadata Extension ID: {}", ut_metadata);
+            if let Some(length) = magnet_link.length {
+                println!("Length: {}", length);
             }
-        }
-        Commands::MagnetInfo { magnet_link } => {
-            let magnet_link: MagnetLink = magnet_link.parse().unwrap();
-            let placeholder_torrent = Torrent {
-                hash: magnet_link.hash,
-                announce: magnet_link.tracker_url.clone(),
-                length: 999, // fake length to make the peer happy
-                ..Default::default()
-            };
 
-            let peer_id = generate_peer_id();
-            let peers = peers::fetch_peers(&placeholder_torrent, &peer_id).unwrap();
-            let peer_ip = peers[0];
+            for tracker in magnet_link.trackers.iter().skip(1) {
+                println!("Tracker URL: {}", tracker);
+            }
 
-            let mut stream = match TcpStream::connect(peer_ip).await {
-                Ok(stream) => stream,
-                Err(err) => {
-                    eprintln!("Error connecting to peer: {}", err);
-                    std::process::exit(1);
-                }
-            };
+            for peer in &magnet_link.peers {
+                println!("Peer: {}", peer);
+            }
 
-            let base_handshake_result = match peers::shake_hands(
-                &mut stream,
-                &placeholder_torrent,
-                &peer_id,
-                HandshakeReservedBytes::ExtensionsEnabled,
-            )
-            .await
-            {
-                Ok(base_handshake_result) => base_handshake_result,
-                Err(err) => {
-                    eprintln!("Error shaking hands: {}", err);
-                    std::process::exit(1);
-                }
-            };
+            for web_seed in &magnet_link.web_seeds {
+                println!("Web Seed: {}", web_seed);
+            }
 
-            // Recieve the bitfield message
-            match PeerMessage::read(&mut stream).await {
-                Ok(message) => match message.id {
-                    PeerMessageId::Bitfield => {}
-                    _ => {
-                        eprintln!("Unexpected message: {:?}", message);
-                        std::process::exit(1);
-                    }
-                },
-                Err(err) => {
-                    eprintln!("Error reading bitfield message: {}", err);
-                    std::process::exit(1);
-                }
+            if !magnet_link.select_only.is_empty() {
+                println!(
+                    "Select Only: {}",
+                    format_select_only(&magnet_link.select_only)
+                );
             }
+        }
+        Commands::MagnetHandshake { magnet_link } => {
+            let magnet_link = parse_magnet_link(magnet_link);
 
-            if !base_handshake_result
-                .reserved_bytes
-                .contains(HandshakeReservedBytes::ExtensionsEnabled)
-            {
-                return;
+            if let Err(err) = magnet_handshake(&magnet_link).await {
+                eprintln!("Error shaking hands: {:#}", err);
+                std::process::exit(1);
             }
+        }
+        Commands::MagnetInfo { magnet_link } => {
+            let magnet_link = parse_magnet_link(magnet_link);
+            let peer_id = generate_peer_id();
 
-            let extensions = match peers::shake_hands_extension(&mut stream).await {
-                Ok(extensions) => extensions,
+            let torrent = match peers::magnet_to_torrent(&magnet_link, &peer_id).await {
+                Ok(torrent) => torrent,
                 Err(err) => {
-                    eprintln!("Error shaking hands for extensions: {}", err);
+                    eprintln!("Error fetching metadata: {:#}", err);
                     std::process::exit(1);
                 }
             };
 
-            if let Some(peer_ut_metadata_id) = extensions.ut_metadata {
-                let dictionary =
-                    HashMap::from([("msg_type".to_string(), 0_u8), ("piece".to_string(), 0_u8)]);
-
-                let mut payload = vec![peer_ut_metadata_id];
-                payload.extend_from_slice(&serde_bencode::to_bytes(&dictionary).unwrap());
+            println!("Tracker URL: {}", torrent.announce);
+            println!("Length: {}", torrent.length);
+            println!("Info Hash: {}", torrent.hash);
+            println!("Piece Length: {}", torrent.piece_length);
+            println!("Piece Hashes: \n{}", torrent.piece_hashes.join("\n"));
+        }
+        Commands::MagnetToTorrent {
+            magnet_link,
+            output_path,
+        } => {
+            let magnet_link = parse_magnet_link(magnet_link);
 
-                let request_message = PeerMessage {
-                    id: PeerMessageId::Extension,
-                    payload,
-                };
+            let peer_id = generate_peer_id();
 
-                if let Err(err) = request_message.send(&mut stream).await {
-                    eprintln!("Error sending request: {}", err);
+            let torrent = match peers::magnet_to_torrent(&magnet_link, &peer_id).await {
+                Ok(torrent) => torrent,
+                Err(err) => {
+                    eprintln!("Error fetching metadata: {:#}", err);
                     std::process::exit(1);
                 }
+            };
 
-                if let Ok(message) = PeerMessage::read(&mut stream).await {
-                    let message = match message.id {
-                        PeerMessageId::Extension => message,
-                        _ => {
-                            eprintln!("Unexpected message: {:?}", message);
-                            std::process::exit(1);
-                        }
-                    };
-
-                    let extension_message = ExtensionMessage::from_bytes(&message.payload).unwrap();
-                    let metadata_length =
-                        *extension_message.payload.get("total_size").unwrap() as usize;
-
-                    let start_index = message.payload.len() - metadata_length;
-                    let metadata = match serde_bencode::from_bytes(&message.payload[start_index..])
-                    {
-                        Ok(BValue::Dict(dict)) => dict,
-                        _ => {
-                            eprintln!("Invalid metadata");
-                            std::process::exit(1);
-                        }
-                    };
-
-                    let length = match metadata.get("length".as_bytes()) {
-                        Some(BValue::Int(len)) => *len,
-                        _ => panic!("Torrent file does not contain length entry."),
-                    };
-
-                    let piece_length = match metadata.get("piece length".as_bytes()) {
-                        Some(BValue::Int(len)) => *len,
-                        _ => panic!("Torrent file does not contain piece length entry."),
-                    };
-
-                    let pieces = match metadata.get("pieces".as_bytes()) {
-                        Some(BValue::Bytes(bytes)) => bytes,
-                        _ => panic!("Torrent file does not contain pieces entry."),
-                    };
-
-                    let piece_hashes = pieces.chunks(20).map(const_hex::encode).collect();
+            // The name comes from a peer, so it can't be trusted as a path.
+            let output_path = output_path
+                .clone()
+                .unwrap_or_else(|| format!("{}.torrent", sanitize_component(&torrent.name).0));
 
-                    let hash = calculate_hash(&message.payload[start_index..]);
+            if let Err(err) = std::fs::write(&output_path, torrent.to_bytes()) {
+                eprintln!("Error writing output: {}", err);
+                std::process::exit(1);
+            }
 
-                    let torrent = Torrent {
-                        announce: magnet_link.tracker_url,
-                        length,
-                        piece_length,
-                        piece_hashes,
-                        hash,
-                    };
+            println!("Info Hash: {}", torrent.hash);
 
-                    println!("Tracker URL: {}", torrent.announce);
-                    println!("Length: {}", torrent.length);
-                    println!("Info Hash: {}", torrent.hash);
-                    println!("Piece Length: {}", torrent.piece_length);
-                    println!("Piece Hashes: \n{}", torrent.piece_hashes.join("\n"));
-                }
+            if let Some(hash_v2) = &torrent.hash_v2 {
+                println!("Info Hash v2: {}", hash_v2);
             }
         }
         Commands::MagnetDownloadPiece {
@@ -482,362 +855,234 @@ async fn main() {
             output_path,
             piece_index,
         } => {
-            let magnet_link: MagnetLink = magnet_link.parse().unwrap();
-            let placeholder_torrent = Torrent {
-                hash: magnet_link.hash,
-                announce: magnet_link.tracker_url.clone(),
-                length: 999, // fake length to make the peer happy
-                ..Default::default()
-            };
-
-            let peer_id = generate_peer_id();
-            let peers = peers::fetch_peers(&placeholder_torrent, &peer_id).unwrap();
-            let peer_ip = peers[0];
-
-            let mut stream = match TcpStream::connect(peer_ip).await {
-                Ok(stream) => stream,
-                Err(err) => {
-                    eprintln!("Error connecting to peer: {}", err);
-                    std::process::exit(1);
-                }
-            };
-
-            let base_handshake_result = match peers::shake_hands(
-                &mut stream,
-                &placeholder_torrent,
-                &peer_id,
-                HandshakeReservedBytes::ExtensionsEnabled,
-            )
-            .await
-            {
-                Ok(base_handshake_result) => base_handshake_result,
-                Err(err) => {
-                    eprintln!("Error shaking hands: {}", err);
-                    std::process::exit(1);
-                }
-            };
+            let magnet_link = parse_magnet_link(magnet_link);
+            let output_path = output_path.clone().unwrap_or("/tmp/output".to_string());
 
-            // Recieve the bitfield message
-            match PeerMessage::read(&mut stream).await {
-                Ok(message) => match message.id {
-                    PeerMessageId::Bitfield => {}
-                    _ => {
-                        eprintln!("Unexpected message: {:?}", message);
+            let file_info =
+                match magnet_download(&magnet_link, &output_path, Some(*piece_index)).await {
+                    Ok(file_info) => file_info,
+                    Err(err) => {
+                        eprintln!("Error downloading: {:#}", err);
                         std::process::exit(1);
                     }
-                },
-                Err(err) => {
-                    eprintln!("Error reading bitfield message: {}", err);
-                    std::process::exit(1);
-                }
+                };
+
+            let piece = &file_info.pieces[*piece_index];
+
+            if !piece.is_complete() {
+                eprintln!("Piece is not complete");
+                std::process::exit(1);
             }
 
-            if !base_handshake_result
-                .reserved_bytes
-                .contains(HandshakeReservedBytes::ExtensionsEnabled)
-            {
-                return;
+            if !piece.is_valid() {
+                eprintln!("Piece is not valid");
+                std::process::exit(1);
             }
 
-            let extensions = match peers::shake_hands_extension(&mut stream).await {
-                Ok(extensions) => extensions,
+            let mut file = match File::create(output_path).await {
+                Ok(file) => file,
                 Err(err) => {
-                    eprintln!("Error shaking hands for extensions: {}", err);
+                    eprintln!("Unable to create file: {}", err);
                     std::process::exit(1);
                 }
             };
 
-            if let Some(peer_ut_metadata_id) = extensions.ut_metadata {
-                let dictionary =
-                    HashMap::from([("msg_type".to_string(), 0_u8), ("piece".to_string(), 0_u8)]);
-
-                let mut payload = vec![peer_ut_metadata_id];
-                payload.extend_from_slice(&serde_bencode::to_bytes(&dictionary).unwrap());
-
-                let request_message = PeerMessage {
-                    id: PeerMessageId::Extension,
-                    payload,
-                };
-
-                if let Err(err) = request_message.send(&mut stream).await {
-                    eprintln!("Error sending request: {}", err);
-                    std::process::exit(1);
-                }
-
-                if let Ok(message) = PeerMessage::read(&mut stream).await {
-                    let message = match message.id {
-                        PeerMessageId::Extension => message,
-                        _ => {
-                            eprintln!("Unexpected message: {:?}", message);
-                            std::process::exit(1);
-                        }
-                    };
-
-                    let extension_message = ExtensionMessage::from_bytes(&message.payload).unwrap();
-                    let metadata_length =
-                        *extension_message.payload.get("total_size").unwrap() as usize;
-
-                    let start_index = message.payload.len() - metadata_length;
-                    let metadata = match serde_bencode::from_bytes(&message.payload[start_index..])
-                    {
-                        Ok(BValue::Dict(dict)) => dict,
-                        _ => {
-                            eprintln!("Invalid metadata");
-                            std::process::exit(1);
-                        }
-                    };
-
-                    let length = match metadata.get("length".as_bytes()) {
-                        Some(BValue::Int(len)) => *len,
-                        _ => panic!("Torrent file does not contain length entry."),
-                    };
-
-                    let piece_length = match metadata.get("piece length".as_bytes()) {
-                        Some(BValue::Int(len)) => *len,
-                        _ => panic!("Torrent file does not contain piece length entry."),
-                    };
-
-                    let pieces = match metadata.get("pieces".as_bytes()) {
-                        Some(BValue::Bytes(bytes)) => bytes,
-                        _ => panic!("Torrent file does not contain pieces entry."),
-                    };
-
-                    let piece_hashes = pieces.chunks(20).map(const_hex::encode).collect();
-
-                    let hash = calculate_hash(&message.payload[start_index..]);
-
-                    let torrent = Torrent {
-                        announce: magnet_link.tracker_url,
-                        length,
-                        piece_length,
-                        piece_hashes,
-                        hash,
-                    };
-
-                    if *piece_index >= torrent.piece_hashes.len() {
-                        eprintln!("Invalid piece index");
-                        std::process::exit(1);
-                    }
-
-                    let output_path = output_path.clone().unwrap_or("/tmp/output".to_string());
-                    let mut file_info = FileInfo::new(output_path.clone(), &torrent);
-
-                    if let Err(err) = PeerMessage::interested().send(&mut stream).await {
-                        eprintln!("Error sending interested: {}", err);
-                        std::process::exit(1);
-                    }
-
-                    loop {
-                        let message = PeerMessage::read(&mut stream).await;
-
-                        if let Err(err) = message {
-                            eprintln!("Error reading message: {}", err);
-                            std::process::exit(1);
-                        }
-
-                        let message = message.unwrap();
-
-                        if let Err(err) = message.process(&mut stream, &mut file_info).await {
-                            eprintln!("Error processing message: {}", err);
-                            std::process::exit(1);
-                        }
-
-                        if file_info.is_complete() {
-                            break;
-                        }
-                    }
-
-                    let piece = &file_info.pieces[*piece_index];
-
-                    if !piece.is_complete() {
-                        eprintln!("Piece is not complete");
-                        std::process::exit(1);
-                    }
-
-                    if !piece.is_valid() {
-                        eprintln!("Piece is not valid");
-                        std::process::exit(1);
-                    }
-
-                    let mut file = File::create(output_path).await.unwrap();
-
-                    if let Err(err) = piece.write(&mut file).await {
-                        eprintln!("Unable to save file to disk: {}", err);
-                        std::process::exit(1);
-                    }
-                }
+            if let Err(err) = piece.write(&mut file).await {
+                eprintln!("Unable to save file to disk: {}", err);
+                std::process::exit(1);
             }
         }
         Commands::MagnetDownload {
             magnet_link,
             output_path,
         } => {
-            let magnet_link: MagnetLink = magnet_link.parse().unwrap();
-            let placeholder_torrent = Torrent {
-                hash: magnet_link.hash,
-                announce: magnet_link.tracker_url.clone(),
-                length: 999, // fake length to make the peer happy
-                ..Default::default()
-            };
-
-            let peer_id = generate_peer_id();
-            let peers = peers::fetch_peers(&placeholder_torrent, &peer_id).unwrap();
-            let peer_ip = peers[0];
-
-            let mut stream = match TcpStream::connect(peer_ip).await {
-                Ok(stream) => stream,
-                Err(err) => {
-                    eprintln!("Error connecting to peer: {}", err);
-                    std::process::exit(1);
-                }
-            };
+            let magnet_link = parse_magnet_link(magnet_link);
+            let output_path = output_path.clone().unwrap_or("/tmp/output".to_string());
 
-            let base_handshake_result = match peers::shake_hands(
-                &mut stream,
-                &placeholder_torrent,
-                &peer_id,
-                HandshakeReservedBytes::ExtensionsEnabled,
-            )
-            .await
-            {
-                Ok(base_handshake_result) => base_handshake_result,
+            let file_info = match magnet_download(&magnet_link, &output_path, None).await {
+                Ok(file_info) => file_info,
                 Err(err) => {
-                    eprintln!("Error shaking hands: {}", err);
+                    eprintln!("Error downloading: {:#}", err);
                     std::process::exit(1);
                 }
             };
 
-            // Recieve the bitfield message
-            match PeerMessage::read(&mut stream).await {
-                Ok(message) => match message.id {
-                    PeerMessageId::Bitfield => {}
-                    _ => {
-                        eprintln!("Unexpected message: {:?}", message);
-                        std::process::exit(1);
+            match file_info.save_to_disk().await {
+                Ok(changes) => {
+                    for change in changes {
+                        eprintln!("Renamed {}", change);
                     }
-                },
+                }
                 Err(err) => {
-                    eprintln!("Error reading bitfield message: {}", err);
+                    eprintln!("Unable to save file to disk: {}", err);
                     std::process::exit(1);
                 }
             }
+        }
+    }
+}
 
-            if !base_handshake_result
-                .reserved_bytes
-                .contains(HandshakeReservedBytes::ExtensionsEnabled)
-            {
-                return;
-            }
+fn parse_magnet_link(magnet_link: &str) -> MagnetLink {
+    match magnet_link.parse() {
+        Ok(magnet_link) => magnet_link,
+        Err(err) => {
+            eprintln!("Invalid magnet link: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
 
-            let extensions = match peers::shake_hands_extension(&mut stream).await {
-                Ok(extensions) => extensions,
-                Err(err) => {
-                    eprintln!("Error shaking hands for extensions: {}", err);
-                    std::process::exit(1);
-                }
-            };
+// Trackers and peers only need the info hash to talk to us.
+fn placeholder_torrent(magnet_link: &MagnetLink) -> Torrent {
+    Torrent {
+        hash: magnet_link.hash,
+        hash_v2: magnet_link.hash_v2,
+        announce: magnet_link.tracker_url().to_string(),
+        announce_list: magnet_link.tracker_tiers(),
+        length: 999, // fake length to make the peer happy
+        ..Default::default()
+    }
+}
 
-            if let Some(peer_ut_metadata_id) = extensions.ut_metadata {
-                let dictionary =
-                    HashMap::from([("msg_type".to_string(), 0_u8), ("piece".to_string(), 0_u8)]);
+// `x.pe` peers first, then whatever the trackers hand out, each with the
+// info hash of the swarm it's in.
+async fn magnet_peers(
+    magnet_link: &MagnetLink,
+    torrent: &Torrent,
+    peer_id: &PeerId,
+) -> Result<Vec<(String, InfoHash)>> {
+    let mut peers = magnet_link
+        .peers
+        .iter()
+        .map(|peer| (peer.clone(), magnet_link.hash))
+        .collect::<Vec<_>>();
+
+    if magnet_link.trackers.is_empty() {
+        return Ok(peers);
+    }
 
-                let mut payload = vec![peer_ut_metadata_id];
-                payload.extend_from_slice(&serde_bencode::to_bytes(&dictionary).unwrap());
+    let tracker_torrent = torrent.clone();
+    let tracker_peer_id = *peer_id;
+
+    // NOTE: trackers are contacted with reqwest's blocking client, which
+    // can't run on the async runtime's own threads.
+    let tracker_peers =
+        tokio::task::spawn_blocking(move || peers::fetch_peers(&tracker_torrent, &tracker_peer_id))
+            .await?;
+
+    match tracker_peers {
+        Ok(tracker_peers) => peers.extend(
+            tracker_peers
+                .iter()
+                .map(|peer| (peer.to_string(), peer.hash)),
+        ),
+        Err(err) if peers.is_empty() => return Err(err),
+        Err(_) => {}
+    }
 
-                let request_message = PeerMessage {
-                    id: PeerMessageId::Extension,
-                    payload,
-                };
+    Ok(peers)
+}
 
-                if let Err(err) = request_message.send(&mut stream).await {
-                    eprintln!("Error sending request: {}", err);
-                    std::process::exit(1);
-                }
-
-                if let Ok(message) = PeerMessage::read(&mut stream).await {
-                    let message = match message.id {
-                        PeerMessageId::Extension => message,
-                        _ => {
-                            eprintln!("Unexpected message: {:?}", message);
-                            std::process::exit(1);
-                        }
-                    };
-
-                    let extension_message = ExtensionMessage::from_bytes(&message.payload).unwrap();
-                    let metadata_length =
-                        *extension_message.payload.get("total_size").unwrap() as usize;
-
-                    let start_index = message.payload.len() - metadata_length;
-                    let metadata = match serde_bencode::from_bytes(&message.payload[start_index..])
-                    {
-                        Ok(BValue::Dict(dict)) => dict,
-                        _ => {
-                            eprintln!("Invalid metadata");
-                            std::process::exit(1);
-                        }
-                    };
-
-                    let length = match metadata.get("length".as_bytes()) {
-                        Some(BValue::Int(len)) => *len,
-                        _ => panic!("Torrent file does not contain length entry."),
-                    };
-
-                    let piece_length = match metadata.get("piece length".as_bytes()) {
-                        Some(BValue::Int(len)) => *len,
-                        _ => panic!("Torrent file does not contain piece length entry."),
-                    };
-
-                    let pieces = match metadata.get("pieces".as_bytes()) {
-                        Some(BValue::Bytes(bytes)) => bytes,
-                        _ => panic!("Torrent file does not contain pieces entry."),
-                    };
-
-                    let piece_hashes = pieces.chunks(20).map(const_hex::encode).collect();
-
-                    let hash = calculate_hash(&message.payload[start_index..]);
-
-                    let torrent = Torrent {
-                        announce: magnet_link.tracker_url,
-                        length,
-                        piece_length,
-                        piece_hashes,
-                        hash,
-                    };
-
-                    let output_path = output_path.clone().unwrap_or("/tmp/output".to_string());
-                    let mut file_info = FileInfo::new(output_path.clone(), &torrent);
-
-                    if let Err(err) = PeerMessage::interested().send(&mut stream).await {
-                        eprintln!("Error sending interested: {}", err);
-                        std::process::exit(1);
-                    }
+async fn magnet_handshake(magnet_link: &MagnetLink) -> Result<()> {
+    let torrent = placeholder_torrent(magnet_link);
+    let peer_id = generate_peer_id();
+
+    let magnet_peers = magnet_peers(magnet_link, &torrent, &peer_id).await?;
+    let (peer, hash) = magnet_peers.first().context("No peers found")?;
+
+    let mut stream = TcpStream::connect(peer).await?;
+
+    let handshake = peers::shake_hands_in_swarm(
+        &mut stream,
+        &torrent,
+        hash,
+        &peer_id,
+        HandshakeReservedBytes::ExtensionsEnabled,
+    )
+    .await?;
+
+    println!("Peer ID: {}", handshake.peer_id);
+
+    let message = PeerMessage::read(&mut stream).await?;
+    anyhow::ensure!(
+        matches!(message.id, PeerMessageId::Bitfield),
+        "Unexpected message: {:?}",
+        message
+    );
+
+    if !handshake
+        .reserved_bytes
+        .contains(HandshakeReservedBytes::ExtensionsEnabled)
+    {
+        return Ok(());
+    }
 
-                    loop {
-                        let message = PeerMessage::read(&mut stream).await;
+    let (extensions, _) = peers::shake_hands_extension(&mut stream).await?;
 
-                        if let Err(err) = message {
-                            eprintln!("Error reading message: {}", err);
-                            std::process::exit(1);
-                        }
+    if let Some(ut_metadata) = extensions.ut_metadata() {
+        println!("Peer Metadata Extension ID: {}", ut_metadata);
+    }
 
-                        let message = message.unwrap();
+    Ok(())
+}
 
-                        if let Err(err) = message.process(&mut stream, &mut file_info).await {
-                            eprintln!("Error processing message: {}", err);
-                            std::process::exit(1);
-                        }
+// The metadata comes from whichever peer can hand it over first, and the
+// pieces from each peer in turn until everything has been downloaded.
+async fn magnet_download(
+    magnet_link: &MagnetLink,
+    output_path: &str,
+    piece_index: Option<usize>,
+) -> Result<FileInfo> {
+    let peer_id = generate_peer_id();
+    let torrent = peers::magnet_to_torrent(magnet_link, &peer_id).await?;
+
+    if let Some(piece_index) = piece_index {
+        anyhow::ensure!(
+            piece_index < torrent.piece_hashes.len(),
+            "Invalid piece index"
+        );
+    }
 
-                        if file_info.is_complete() {
-                            break;
-                        }
-                    }
+    let mut file_info = FileInfo::new(output_path.to_string(), &torrent)?;
+    let mut last_error = None;
 
-                    if let Err(err) = file_info.save_to_disk().await {
-                        eprintln!("Unable to save file to disk: {}", err);
-                        std::process::exit(1);
-                    }
-                }
-            }
+    for (peer, hash) in magnet_peers(magnet_link, &torrent, &peer_id).await? {
+        // NOTE: blocks we already have aren't asked for again, so the next
+        // peer picks up where the last one left off.
+        match download_from(&peer, &torrent, &hash, &peer_id, &mut file_info).await {
+            Ok(()) => return Ok(file_info),
+            Err(err) => last_error = Some(err.context(format!("{} failed", peer))),
         }
     }
+
+    match last_error {
+        Some(err) => Err(err),
+        None => anyhow::bail!("No peers found for {}", magnet_link.hash),
+    }
+}
+
+async fn download_from(
+    peer: &str,
+    torrent: &Torrent,
+    hash: &InfoHash,
+    peer_id: &PeerId,
+    file_info: &mut FileInfo,
+) -> Result<()> {
+    let mut stream = TcpStream::connect(peer).await?;
+
+    peers::shake_hands_in_swarm(
+        &mut stream,
+        torrent,
+        hash,
+        peer_id,
+        HandshakeReservedBytes::empty(),
+    )
+    .await?;
+
+    while !file_info.is_complete() {
+        let message = PeerMessage::read(&mut stream).await?;
+        message.process(&mut stream, file_info).await?;
+    }
+
+    Ok(())
 }
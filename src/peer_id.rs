@@ -0,0 +1,51 @@
+use crate::id_encoding::{base32_encode, decode_id, ParseIdError};
+use rand::distributions::{Alphanumeric, Distribution};
+use std::{fmt, str::FromStr};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PeerId(pub [u8; 20]);
+
+impl PeerId {
+    // Random alphanumeric characters, so it's readable in tracker logs too.
+    pub fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        Self(std::array::from_fn(|_| Alphanumeric.sample(&mut rng)))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0)
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = ParseIdError;
+
+    fn from_str(id: &str) -> Result<Self, ParseIdError> {
+        decode_id(id).map(Self)
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", const_hex::encode(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_id() {
+        let peer_id = PeerId(*b"00112233445566778899");
+
+        assert_eq!(hex::encode("00112233445566778899"), peer_id.to_string());
+        assert_eq!(Ok(peer_id), peer_id.to_string().parse());
+        assert_eq!(Ok(peer_id), peer_id.to_base32().parse());
+        assert!(PeerId::random().0.iter().all(u8::is_ascii_alphanumeric));
+    }
+}
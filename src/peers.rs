@@ -1,21 +1,50 @@
 mod fetch_peers;
 pub use fetch_peers::fetch_peers;
+pub use fetch_peers::fetch_peers_from;
+pub use fetch_peers::SwarmPeer;
+
+mod tracker_tiers;
+pub use tracker_tiers::TrackerTiers;
 
 mod shake_hands;
 pub use shake_hands::shake_hands;
+pub use shake_hands::shake_hands_in_swarm;
 pub use shake_hands::HandshakeReservedBytes;
 
 mod extension_handshake;
+pub use extension_handshake::extension_handshake;
 pub use extension_handshake::shake_hands_extension;
+pub use extension_handshake::ExtensionHandshake;
 pub use extension_handshake::SupportedExtensions;
 
+mod extension_registry;
+pub use extension_registry::Extension;
+pub use extension_registry::ExtensionRegistry;
+
+mod ut_metadata;
+pub use ut_metadata::UtMetadata;
+pub use ut_metadata::UT_METADATA;
+
 mod peer_message;
 pub use peer_message::PeerMessage;
 pub use peer_message::PeerMessageId;
 
 mod extension_messages;
+pub use extension_messages::split_header;
 pub use extension_messages::ExtensionMessage;
 pub use extension_messages::ExtensionMessageId;
 
+mod fetch_metadata;
+pub use fetch_metadata::fetch_metadata;
+pub use fetch_metadata::fetch_metadata_from;
+pub use fetch_metadata::magnet_to_torrent;
+pub use fetch_metadata::request_metadata;
+pub use fetch_metadata::verify_metadata;
+pub use fetch_metadata::METADATA_PIECE_SIZE;
+
+mod serve_metadata;
+pub use serve_metadata::metadata_response;
+pub use serve_metadata::serve_metadata;
+
 mod generate_peer_id;
 pub use generate_peer_id::generate_peer_id;
@@ -1,60 +1,77 @@
+use super::{ExtensionRegistry, PeerMessage, UT_METADATA};
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::BTreeMap;
+use tokio::io::{AsyncRead, AsyncWrite};
 
+// The extension handshake with only the built-in extensions. Use an
+// `ExtensionRegistry` directly to add more. Also returns anything the peer
+// sent before its handshake, see `ExtensionRegistry::shake_hands`.
 pub async fn shake_hands_extension(
     stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
-) -> Result<SupportedExtensions> {
-    let dictionary = ExtensionDictionary {
-        m: SupportedExtensions::my_supported(),
-    };
+) -> Result<(SupportedExtensions, Vec<PeerMessage>)> {
+    let mut registry = ExtensionRegistry::new();
+    let pending = registry.shake_hands(stream).await?;
 
-    let payload = serde_bencode::to_bytes(&dictionary).unwrap();
-
-    let mut handshake = Vec::<u8>::new();
-    handshake.extend_from_slice(&u32::to_be_bytes(payload.len() as u32 + 2)[..]);
-    handshake.push(u8::to_be(20));
-    handshake.push(u8::to_be(0));
-    handshake.extend_from_slice(&payload);
-
-    stream.write_all(&handshake).await?;
-
-    let response_size = stream.read_u32().await?;
-
-    let mut response_buffer = vec![0_u8; response_size as usize];
-    stream.read_exact(&mut response_buffer).await?;
-
-    let message_id = u8::from_be(response_buffer[0]);
-    anyhow::ensure!(message_id == 20, "Invalid message id");
-
-    let extension_message_id = u8::from_be(response_buffer[1]);
-    anyhow::ensure!(extension_message_id == 0, "Invalid message id");
-
-    let response_dictionary: ExtensionDictionary =
-        serde_bencode::from_bytes(&response_buffer[2..])?;
-
-    Ok(response_dictionary.m)
+    Ok((registry.peer_extensions().clone(), pending))
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
-pub struct SupportedExtensions {
-    pub ut_metadata: Option<u8>,
+// Our side of the BEP 10 handshake, which is extension message 0. Use
+// `ExtensionRegistry::with_metadata` to advertise `metadata_size` as well.
+pub fn extension_handshake() -> Result<PeerMessage> {
+    ExtensionRegistry::new().handshake()
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
-struct ExtensionDictionary {
+// The `m` dictionary, from extension names to message ids.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(transparent)]
+pub struct SupportedExtensions(BTreeMap<String, u8>);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ExtensionHandshake {
     pub m: SupportedExtensions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_size: Option<u64>,
 }
 
 impl SupportedExtensions {
     pub fn all_unsupported() -> Self {
-        Self { ut_metadata: None }
+        Self::default()
     }
 
     pub fn my_supported() -> Self {
-        Self {
-            ut_metadata: Some(1),
-        }
+        ExtensionRegistry::new().supported().clone()
+    }
+
+    // NOTE: BEP 10 uses an id of 0 to turn an extension off.
+    pub fn get(&self, name: &str) -> Option<u8> {
+        self.0.get(name).copied().filter(|id| *id != 0)
+    }
+
+    pub fn ut_metadata(&self) -> Option<u8> {
+        self.get(UT_METADATA)
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, id: u8) {
+        self.0.insert(name.into(), id);
+    }
+
+    // NOTE: BEP 10 lets later handshakes only list what changed, so anything
+    // `other` leaves out is kept as is. An id of 0 still turns it off.
+    pub fn merge(&mut self, other: &SupportedExtensions) {
+        self.0
+            .extend(other.0.iter().map(|(name, id)| (name.clone(), *id)));
+    }
+}
+
+impl<const N: usize> From<[(&str, u8); N]> for SupportedExtensions {
+    fn from(extensions: [(&str, u8); N]) -> Self {
+        Self(
+            extensions
+                .into_iter()
+                .map(|(name, id)| (name.to_string(), id))
+                .collect(),
+        )
     }
 }
 
@@ -62,15 +79,18 @@ impl SupportedExtensions {
 mod test {
     use tokio_test::assert_ok;
 
+    use crate::bencode;
+
     use super::*;
 
     #[tokio::test]
     async fn test_shake_hands_extension() {
-        let dictionary = ExtensionDictionary {
+        let dictionary = ExtensionHandshake {
             m: SupportedExtensions::my_supported(),
+            metadata_size: None,
         };
 
-        let payload = serde_bencode::to_bytes(&dictionary).unwrap();
+        let payload = bencode::to_bytes(&dictionary).unwrap();
 
         let mut handshake = Vec::<u8>::new();
         handshake.extend_from_slice(&u32::to_be_bytes(payload.len() as u32 + 2)[..]);
@@ -86,9 +106,25 @@ mod test {
         let expected_extensions = SupportedExtensions::my_supported();
 
         let actual_extensions = shake_hands_extension(&mut stream).await;
-        assert_ok!(actual_extensions);
+        assert_ok!(actual_extensions.as_ref());
 
-        let actual_extensions = actual_extensions.unwrap();
+        let (actual_extensions, pending) = actual_extensions.unwrap();
         assert_eq!(expected_extensions, actual_extensions);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_extension_handshake_advertises_metadata_size() {
+        assert_eq!(
+            b"\0d1:md11:ut_metadatai1ee13:metadata_sizei1234ee".to_vec(),
+            ExtensionRegistry::with_metadata(vec![b'x'; 1234])
+                .handshake()
+                .unwrap()
+                .payload
+        );
+        assert_eq!(
+            b"\0d1:md11:ut_metadatai1eee".to_vec(),
+            extension_handshake().unwrap().payload
+        );
     }
 }
@@ -1,25 +1,181 @@
+use super::{ExtensionHandshake, PeerMessage, PeerMessageId, SupportedExtensions};
+use crate::bencode::{self, DecodeOptions, ValueRef};
 use anyhow::Result;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::{collections::HashMap, fmt};
+use std::{collections::BTreeMap, fmt};
 
-#[derive(Clone, Debug)]
-pub struct ExtensionMessage {
-    pub peer_extension_id: u8,
-    pub payload: HashMap<String, u8>,
+// A BEP 10 extension message, which is the payload of a message with id 20.
+// The first byte says which extension it's for: 0 is the handshake, and the
+// rest are the ids each side picked in its handshake's `m` dictionary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExtensionMessage {
+    Handshake(ExtensionHandshake),
+    // ut_metadata (BEP 9)
+    MetadataRequest {
+        piece: u32,
+    },
+    MetadataData {
+        piece: u32,
+        total_size: u64,
+        data: Vec<u8>,
+    },
+    MetadataReject {
+        piece: u32,
+    },
+    // Anything we don't understand, kept as is.
+    Other {
+        extension_id: u8,
+        payload: Vec<u8>,
+    },
 }
 
 impl ExtensionMessage {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        let peer_extension_id = u8::from_be(bytes[0]);
-        let payload = serde_bencode::from_bytes(&bytes[1..])?;
+    // NOTE: incoming messages use the ids from our own handshake, so that's
+    // what they're matched against here.
+    pub fn from_bytes(bytes: &[u8], our_extensions: &SupportedExtensions) -> Result<Self> {
+        let (extension_id, payload) = match bytes.split_first() {
+            Some((extension_id, payload)) => (*extension_id, payload),
+            None => anyhow::bail!("Empty extension message"),
+        };
 
-        Ok(Self {
-            peer_extension_id,
-            payload,
+        if extension_id == 0 {
+            let handshake = bencode::from_bytes_with_options(payload, DecodeOptions::untrusted())?;
+
+            return Ok(Self::Handshake(handshake));
+        }
+
+        if Some(extension_id) == our_extensions.ut_metadata() {
+            return Self::metadata_from_bytes(payload);
+        }
+
+        Ok(Self::Other {
+            extension_id,
+            payload: payload.to_vec(),
         })
     }
+
+    // A ut_metadata message, without the leading extension id.
+    pub(crate) fn metadata_from_bytes(payload: &[u8]) -> Result<Self> {
+        let (header, data) = split_header(payload)?;
+
+        let get_int = |key: &str| -> Result<i64> {
+            match header.get(key).and_then(|value| value.as_int()) {
+                Some(value) => Ok(value),
+                None => anyhow::bail!("ut_metadata message is missing {}", key),
+            }
+        };
+
+        let msg_type = u8::try_from(get_int("msg_type")?)
+            .ok()
+            .and_then(|msg_type| ExtensionMessageId::try_from(msg_type).ok());
+
+        let piece = match u32::try_from(get_int("piece")?) {
+            Ok(piece) => piece,
+            Err(_) => anyhow::bail!("Invalid ut_metadata piece"),
+        };
+
+        Ok(match msg_type {
+            Some(ExtensionMessageId::Request) => Self::MetadataRequest { piece },
+            Some(ExtensionMessageId::Data) => Self::MetadataData {
+                piece,
+                total_size: match u64::try_from(get_int("total_size")?) {
+                    Ok(total_size) => total_size,
+                    Err(_) => anyhow::bail!("Invalid ut_metadata total_size"),
+                },
+                data: data.to_vec(),
+            },
+            Some(ExtensionMessageId::Reject) => Self::MetadataReject { piece },
+            None => anyhow::bail!("Unknown ut_metadata message type"),
+        })
+    }
+
+    // Outgoing messages have to use the ids from the peer's handshake.
+    pub fn to_bytes(&self, peer_extensions: &SupportedExtensions) -> Result<Vec<u8>> {
+        let extension_id = match self {
+            Self::Handshake(_) => 0,
+            Self::MetadataRequest { .. }
+            | Self::MetadataData { .. }
+            | Self::MetadataReject { .. } => peer_ut_metadata(peer_extensions)?,
+            Self::Other { extension_id, .. } => *extension_id,
+        };
+
+        let mut bytes = vec![extension_id];
+        bytes.extend_from_slice(&self.payload()?);
+
+        Ok(bytes)
+    }
+
+    // Everything after the extension id.
+    pub fn payload(&self) -> Result<Vec<u8>> {
+        let (header, data) = match self {
+            Self::Handshake(handshake) => (bencode::to_bytes(handshake)?, &[][..]),
+            Self::MetadataRequest { piece } => (
+                metadata_header(ExtensionMessageId::Request, *piece, None)?,
+                &[][..],
+            ),
+            Self::MetadataData {
+                piece,
+                total_size,
+                data,
+            } => (
+                metadata_header(ExtensionMessageId::Data, *piece, Some(*total_size))?,
+                &data[..],
+            ),
+            Self::MetadataReject { piece } => (
+                metadata_header(ExtensionMessageId::Reject, *piece, None)?,
+                &[][..],
+            ),
+            Self::Other { payload, .. } => (Vec::new(), &payload[..]),
+        };
+
+        let mut payload = header;
+        payload.extend_from_slice(data);
+
+        Ok(payload)
+    }
+
+    pub fn to_peer_message(&self, peer_extensions: &SupportedExtensions) -> Result<PeerMessage> {
+        Ok(PeerMessage {
+            id: PeerMessageId::Extension,
+            payload: self.to_bytes(peer_extensions)?,
+        })
+    }
+}
+
+// Some extension messages, like ut_metadata's data, have raw bytes straight
+// after the bencoded dictionary.
+pub fn split_header(payload: &[u8]) -> Result<(ValueRef<'_>, &[u8])> {
+    let header = bencode::parse_with_options(payload, DecodeOptions::untrusted())?;
+    let rest = &payload[header.span().end..];
+
+    Ok((header, rest))
 }
 
+fn peer_ut_metadata(peer_extensions: &SupportedExtensions) -> Result<u8> {
+    match peer_extensions.ut_metadata() {
+        Some(id) => Ok(id),
+        None => anyhow::bail!("Peer doesn't support ut_metadata"),
+    }
+}
+
+fn metadata_header(
+    msg_type: ExtensionMessageId,
+    piece: u32,
+    total_size: Option<u64>,
+) -> Result<Vec<u8>> {
+    let mut header = BTreeMap::from([
+        ("msg_type", u8::from(msg_type) as u64),
+        ("piece", piece as u64),
+    ]);
+
+    if let Some(total_size) = total_size {
+        header.insert("total_size", total_size);
+    }
+
+    Ok(bencode::to_bytes(&header)?)
+}
+
+// The `msg_type` of a ut_metadata message.
 #[derive(Copy, Clone, Debug, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum ExtensionMessageId {
@@ -33,3 +189,109 @@ impl fmt::Display for ExtensionMessageId {
         write!(f, "{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_bytes() {
+        let mut data = b"\x01d8:msg_typei1e5:piecei2e10:total_sizei92063ee".to_vec();
+        data.extend_from_slice(b"trailing bytes");
+
+        let messages = [
+            (
+                b"\x01d8:msg_typei0e5:piecei3ee".to_vec(),
+                ExtensionMessage::MetadataRequest { piece: 3 },
+            ),
+            (
+                data,
+                ExtensionMessage::MetadataData {
+                    piece: 2,
+                    total_size: 92063,
+                    data: b"trailing bytes".to_vec(),
+                },
+            ),
+            (
+                b"\x01d8:msg_typei2e5:piecei0ee".to_vec(),
+                ExtensionMessage::MetadataReject { piece: 0 },
+            ),
+            (
+                b"\0d1:md11:ut_metadatai3ee13:metadata_sizei92063ee".to_vec(),
+                ExtensionMessage::Handshake(ExtensionHandshake {
+                    m: SupportedExtensions::from([("ut_metadata", 3)]),
+                    metadata_size: Some(92063),
+                }),
+            ),
+            (
+                b"\x07d5:added0:e".to_vec(),
+                ExtensionMessage::Other {
+                    extension_id: 7,
+                    payload: b"d5:added0:e".to_vec(),
+                },
+            ),
+        ];
+
+        let ours = SupportedExtensions::from([("ut_metadata", 1)]);
+
+        for (bytes, expected) in messages {
+            assert_eq!(
+                expected,
+                ExtensionMessage::from_bytes(&bytes, &ours).unwrap()
+            );
+        }
+
+        // Without ut_metadata on our side, it's just another extension.
+        assert!(matches!(
+            ExtensionMessage::from_bytes(
+                b"\x01d8:msg_typei0e5:piecei3ee",
+                &SupportedExtensions::all_unsupported()
+            ),
+            Ok(ExtensionMessage::Other {
+                extension_id: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        let errors = [
+            &b""[..],
+            b"\x01d5:piecei0ee",
+            b"\x01d8:msg_typei9e5:piecei0ee",
+            b"\x01d8:msg_typei0e5:piecei-1ee",
+            b"\x01d8:msg_typei1e5:piecei0ee",
+            b"\x01not bencode",
+        ];
+
+        let ours = SupportedExtensions::from([("ut_metadata", 1)]);
+
+        for bytes in errors {
+            assert!(
+                ExtensionMessage::from_bytes(bytes, &ours).is_err(),
+                "{:?}",
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_bytes_uses_peer_ids() {
+        let peer_extensions = SupportedExtensions::from([("ut_metadata", 3)]);
+
+        let message = ExtensionMessage::MetadataData {
+            piece: 1,
+            total_size: 70_000,
+            data: vec![1, 2, 3],
+        };
+
+        let mut expected = b"\x03d8:msg_typei1e5:piecei1e10:total_sizei70000ee".to_vec();
+        expected.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(expected, message.to_bytes(&peer_extensions).unwrap());
+        assert!(message
+            .to_bytes(&SupportedExtensions::all_unsupported())
+            .is_err());
+    }
+}
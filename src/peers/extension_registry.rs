@@ -0,0 +1,381 @@
+use super::{
+    ExtensionHandshake, ExtensionMessage, PeerMessage, PeerMessageId, SupportedExtensions,
+    UtMetadata,
+};
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+// A BEP 10 extension. Messages sent to the extension's id are handed to it
+// without the leading id byte.
+pub trait Extension: Send + Sync {
+    // The key the extension goes by in the handshake's `m` dictionary.
+    fn name(&self) -> &str;
+
+    // Called once the peer's handshake arrives, even if the peer doesn't
+    // support this extension.
+    fn on_handshake(&mut self, _handshake: &ExtensionHandshake) -> Result<()> {
+        Ok(())
+    }
+
+    // Whatever is returned is sent back to the same extension on the peer's
+    // side, one message per payload.
+    fn on_message(&mut self, payload: &[u8]) -> Result<Vec<Vec<u8>>>;
+}
+
+// The extensions we support on a single connection. Hands out our local
+// message ids, builds the `m` dictionary, and keeps track of the peer's.
+pub struct ExtensionRegistry {
+    extensions: Vec<Box<dyn Extension>>,
+    // Our `m` dictionary, kept up to date by `register`.
+    supported: SupportedExtensions,
+    peer_extensions: SupportedExtensions,
+    // The size of the info dictionary ut_metadata serves, if it has one.
+    metadata_size: Option<usize>,
+}
+
+impl Default for ExtensionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExtensionRegistry {
+    // Only ut_metadata, without any metadata to hand out.
+    pub fn new() -> Self {
+        Self::with_metadata(Vec::new())
+    }
+
+    // Only ut_metadata, serving `info` to peers that ask for it.
+    pub fn with_metadata(info: Vec<u8>) -> Self {
+        // NOTE: the size comes from the same bytes ut_metadata serves, so the
+        // handshake can't advertise anything else.
+        let metadata_size = (!info.is_empty()).then_some(info.len());
+
+        let ut_metadata = UtMetadata::new(info);
+
+        Self {
+            supported: SupportedExtensions::from([(ut_metadata.name(), 1)]),
+            extensions: vec![Box::new(ut_metadata)],
+            peer_extensions: SupportedExtensions::all_unsupported(),
+            metadata_size,
+        }
+    }
+
+    // Returns the id the peer should use to reach the extension.
+    pub fn register(&mut self, extension: Box<dyn Extension>) -> Result<u8> {
+        let name = extension.name();
+
+        anyhow::ensure!(
+            self.supported.get(name).is_none(),
+            "Extension {} is already registered",
+            name
+        );
+
+        let id = match u8::try_from(self.extensions.len() + 1) {
+            Ok(id) => id,
+            Err(_) => anyhow::bail!("Too many extensions"),
+        };
+
+        self.supported.insert(name, id);
+        self.extensions.push(extension);
+
+        Ok(id)
+    }
+
+    // Our `m` dictionary.
+    pub fn supported(&self) -> &SupportedExtensions {
+        &self.supported
+    }
+
+    // What the peer told us in its handshakes, or nothing if it hasn't yet.
+    pub fn peer_extensions(&self) -> &SupportedExtensions {
+        &self.peer_extensions
+    }
+
+    // Only advertises `metadata_size` if we have the info dictionary, so
+    // that peers know they can fetch it from us.
+    pub fn handshake(&self) -> Result<PeerMessage> {
+        let handshake = ExtensionMessage::Handshake(ExtensionHandshake {
+            m: self.supported.clone(),
+            metadata_size: self.metadata_size.map(|size| size as u64),
+        });
+
+        // The handshake always goes out as extension 0, whatever the peer supports.
+        handshake.to_peer_message(&SupportedExtensions::all_unsupported())
+    }
+
+    // Sends our handshake and waits for the peer's, which ends up in
+    // `peer_extensions`. Whatever else the peer sends in the meantime is
+    // returned in order, for the caller to deal with.
+    pub async fn shake_hands(
+        &mut self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    ) -> Result<Vec<PeerMessage>> {
+        self.handshake()?.send(stream).await?;
+
+        let mut pending = Vec::new();
+
+        loop {
+            let message = PeerMessage::read(stream).await?;
+
+            match message.id {
+                PeerMessageId::KeepAlive => continue,
+                PeerMessageId::Extension => {}
+                _ => {
+                    pending.push(message);
+                    continue;
+                }
+            }
+
+            match self.dispatch(stream, &message.payload).await? {
+                Some(ExtensionMessage::Handshake(_)) => return Ok(pending),
+                Some(_) => pending.push(message),
+                None => {}
+            }
+        }
+    }
+
+    // Handles the payload of an incoming message with id 20. Messages for a
+    // registered extension go to it, and anything it doesn't take care of
+    // entirely, like ut_metadata data, is handed back.
+    pub async fn dispatch(
+        &mut self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        payload: &[u8],
+    ) -> Result<Option<ExtensionMessage>> {
+        let message = ExtensionMessage::from_bytes(payload, &self.supported)?;
+
+        if let ExtensionMessage::Handshake(handshake) = &message {
+            for extension in &mut self.extensions {
+                extension.on_handshake(handshake)?;
+            }
+
+            self.peer_extensions.merge(&handshake.m);
+
+            return Ok(Some(message));
+        }
+
+        // NOTE: `from_bytes` has already rejected an empty payload.
+        let extension = (payload[0] as usize)
+            .checked_sub(1)
+            .and_then(|index| self.extensions.get_mut(index));
+
+        let extension = match extension {
+            Some(extension) => extension,
+            None => return Ok(Some(message)),
+        };
+
+        let name = extension.name().to_string();
+        let replies = extension.on_message(&payload[1..])?;
+
+        // NOTE: a peer can message one of our extensions without supporting it
+        // itself, in which case there's no id to answer on.
+        if self.peer_extensions.get(&name).is_some() {
+            for reply in replies {
+                self.send(stream, &name, reply).await?;
+            }
+        }
+
+        match message {
+            ExtensionMessage::Other { .. } => Ok(None),
+            message => Ok(Some(message)),
+        }
+    }
+
+    // Sends a message to one of the peer's extensions, by name.
+    pub async fn send(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        name: &str,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        let extension_id = match self.peer_extensions.get(name) {
+            Some(extension_id) => extension_id,
+            None => anyhow::bail!("Peer doesn't support {}", name),
+        };
+
+        ExtensionMessage::Other {
+            extension_id,
+            payload,
+        }
+        .to_peer_message(&self.peer_extensions)?
+        .send(stream)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replies to every message with the same payload, reversed.
+    struct Reverse;
+
+    impl Extension for Reverse {
+        fn name(&self) -> &str {
+            "lt_reverse"
+        }
+
+        fn on_message(&mut self, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+            Ok(vec![payload.iter().rev().copied().collect()])
+        }
+    }
+
+    fn message(id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut message = (payload.len() as u32 + 1).to_be_bytes().to_vec();
+        message.push(id);
+        message.extend_from_slice(payload);
+        message
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = ExtensionRegistry::new();
+
+        assert_eq!(2, registry.register(Box::new(Reverse)).unwrap());
+        assert!(registry.register(Box::new(Reverse)).is_err());
+        assert!(registry.register(Box::new(UtMetadata::default())).is_err());
+
+        assert_eq!(
+            b"\0d1:md10:lt_reversei2e11:ut_metadatai1eee".to_vec(),
+            registry.handshake().unwrap().payload
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shake_hands_keeps_other_messages() {
+        let mut registry = ExtensionRegistry::new();
+        registry.register(Box::new(Reverse)).unwrap();
+
+        let ours = registry.handshake().unwrap();
+
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&message(20, &ours.payload))
+            .read(&message(4, &[0, 0, 0, 3]))
+            .read(&[0, 0, 0, 0])
+            .read(&message(20, b"\0d1:md10:lt_reversei7e11:ut_metadatai0eee"))
+            .build();
+
+        let pending = registry.shake_hands(&mut stream).await.unwrap();
+        let peer_extensions = registry.peer_extensions();
+
+        assert_eq!(1, pending.len());
+        assert!(matches!(pending[0].id, PeerMessageId::Have));
+        assert_eq!(vec![0, 0, 0, 3], pending[0].payload);
+        assert_eq!(Some(7), peer_extensions.get("lt_reverse"));
+        assert_eq!(None, peer_extensions.ut_metadata());
+    }
+
+    #[tokio::test]
+    async fn test_shake_hands_rejects_huge_messages() {
+        let mut registry = ExtensionRegistry::new();
+        let ours = registry.handshake().unwrap();
+
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&message(20, &ours.payload))
+            .read(&u32::MAX.to_be_bytes())
+            .build();
+
+        assert_eq!(
+            "Message is too large: 4294967295 bytes",
+            registry
+                .shake_hands(&mut stream)
+                .await
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let mut registry = ExtensionRegistry::with_metadata(b"d4:name1:ae".to_vec());
+        registry.register(Box::new(Reverse)).unwrap();
+
+        // Replies go out with the peer's ids for the extensions.
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&message(20, b"\x07cba"))
+            .write(&message(
+                20,
+                b"\x03d8:msg_typei1e5:piecei0e10:total_sizei11eed4:name1:ae",
+            ))
+            .build();
+
+        let handshake = registry
+            .dispatch(&mut stream, b"\0d1:md10:lt_reversei7e11:ut_metadatai3eee")
+            .await
+            .unwrap();
+        assert!(matches!(handshake, Some(ExtensionMessage::Handshake(_))));
+
+        assert_eq!(
+            None,
+            registry.dispatch(&mut stream, b"\x02abc").await.unwrap()
+        );
+
+        // ut_metadata answers the request itself, but it's still passed on.
+        assert_eq!(
+            Some(ExtensionMessage::MetadataRequest { piece: 0 }),
+            registry
+                .dispatch(&mut stream, b"\x01d8:msg_typei0e5:piecei0ee")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(ExtensionMessage::MetadataReject { piece: 0 }),
+            registry
+                .dispatch(&mut stream, b"\x01d8:msg_typei2e5:piecei0ee")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(ExtensionMessage::Other {
+                extension_id: 9,
+                payload: b"abc".to_vec(),
+            }),
+            registry.dispatch(&mut stream, b"\x09abc").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_merges_repeat_handshakes() {
+        let mut registry = ExtensionRegistry::new();
+        let mut stream = tokio_test::io::Builder::new().build();
+
+        registry
+            .dispatch(&mut stream, b"\0d1:md11:ut_metadatai3e6:ut_pexi2eee")
+            .await
+            .unwrap();
+
+        // Only what changed is sent again, with 0 to turn an extension off.
+        registry
+            .dispatch(&mut stream, b"\0d1:md11:lt_donthavei7e6:ut_pexi0eee")
+            .await
+            .unwrap();
+
+        let peer_extensions = registry.peer_extensions();
+
+        assert_eq!(Some(3), peer_extensions.ut_metadata());
+        assert_eq!(Some(7), peer_extensions.get("lt_donthave"));
+        assert_eq!(None, peer_extensions.get("ut_pex"));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_without_peer_support() {
+        let mut registry = ExtensionRegistry::new();
+
+        // The peer never said it supports ut_metadata, so there's no answer.
+        let mut stream = tokio_test::io::Builder::new().build();
+
+        registry
+            .dispatch(&mut stream, b"\0d1:md11:ut_metadatai0eee")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Some(ExtensionMessage::MetadataRequest { piece: 0 }),
+            registry
+                .dispatch(&mut stream, b"\x01d8:msg_typei0e5:piecei0ee")
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,541 @@
+use super::{
+    fetch_peers, shake_hands_in_swarm, ExtensionMessage, ExtensionRegistry, HandshakeReservedBytes,
+    PeerMessage, PeerMessageId,
+};
+use crate::{bencode::Limits, InfoHash, InfoHashV2, MagnetLink, PeerId, Torrent};
+use anyhow::Result;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+// BEP 9 sends the info dictionary in pieces of this size.
+pub const METADATA_PIECE_SIZE: usize = 16 * 1024;
+
+// How long a single peer gets to hand over the whole info dictionary before
+// we give up on it and move on to the next one.
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Fetches the info dictionary for a magnet link over ut_metadata (BEP 9),
+// and turns it into a complete torrent with the magnet's trackers.
+pub async fn magnet_to_torrent(magnet_link: &MagnetLink, peer_id: &PeerId) -> Result<Torrent> {
+    let info = fetch_metadata(magnet_link, peer_id).await?;
+
+    Ok(Torrent::from_magnet(magnet_link, &info)?)
+}
+
+// Downloads the info dictionary, checked against the magnet's info hash.
+// Peers are tried one after another until one of them sends valid metadata.
+pub async fn fetch_metadata(magnet_link: &MagnetLink, peer_id: &PeerId) -> Result<Vec<u8>> {
+    let torrent = placeholder_torrent(magnet_link);
+
+    // `x.pe` peers are tried first, since they were handed to us directly.
+    let mut peers = magnet_link
+        .peers
+        .iter()
+        .map(|peer| (peer.clone(), torrent.hash))
+        .collect::<Vec<_>>();
+    let mut last_error = None;
+
+    if !magnet_link.trackers.is_empty() {
+        let tracker_torrent = torrent.clone();
+        let tracker_peer_id = *peer_id;
+
+        // NOTE: trackers are contacted with reqwest's blocking client, which
+        // can't run on the async runtime's own threads.
+        let tracker_peers =
+            tokio::task::spawn_blocking(move || fetch_peers(&tracker_torrent, &tracker_peer_id))
+                .await?;
+
+        // The `x.pe` peers may still have it, so this isn't fatal yet.
+        match tracker_peers {
+            Ok(tracker_peers) => peers.extend(
+                tracker_peers
+                    .iter()
+                    .map(|peer| (peer.to_string(), peer.hash)),
+            ),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    for (peer, hash) in &peers {
+        let attempt = async {
+            let mut stream = TcpStream::connect(peer).await?;
+            let info = fetch_metadata_from(&mut stream, &torrent, hash, peer_id).await?;
+            verify_metadata(magnet_link, &info)?;
+
+            anyhow::Ok(info)
+        };
+
+        match tokio::time::timeout(PEER_TIMEOUT, attempt).await {
+            Ok(Ok(info)) => return Ok(info),
+            Ok(Err(err)) => last_error = Some(err.context(format!("{} failed", peer))),
+            Err(_) => last_error = Some(anyhow::anyhow!("{} timed out", peer)),
+        }
+    }
+
+    match last_error {
+        Some(err) => Err(err),
+        None => anyhow::bail!("No peers found for {}", magnet_link.hash),
+    }
+}
+
+// Does the handshakes on a fresh connection, then asks the peer for the
+// metadata. `hash` is the swarm the peer came from, see `SwarmPeer`. The
+// result isn't checked against any hash.
+pub async fn fetch_metadata_from(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    torrent: &Torrent,
+    hash: &InfoHash,
+    peer_id: &PeerId,
+) -> Result<Vec<u8>> {
+    let handshake = shake_hands_in_swarm(
+        stream,
+        torrent,
+        hash,
+        peer_id,
+        HandshakeReservedBytes::ExtensionsEnabled,
+    )
+    .await?;
+
+    anyhow::ensure!(
+        handshake
+            .reserved_bytes
+            .contains(HandshakeReservedBytes::ExtensionsEnabled),
+        "Peer doesn't support extensions"
+    );
+
+    let message = PeerMessage::read(stream).await?;
+    anyhow::ensure!(
+        matches!(message.id, PeerMessageId::Bitfield),
+        "Expected a bitfield, got {}",
+        message.id
+    );
+
+    // NOTE: we're only here for the metadata, so anything else the peer sent
+    // first doesn't matter.
+    let mut extensions = ExtensionRegistry::new();
+    extensions.shake_hands(stream).await?;
+
+    anyhow::ensure!(
+        extensions.peer_extensions().ut_metadata().is_some(),
+        "Peer doesn't support ut_metadata"
+    );
+
+    request_metadata(stream, &mut extensions).await
+}
+
+// Requests every piece of the metadata from a peer we've already done the
+// extension handshake with, and puts them back together. Requests from the
+// peer are answered by the ut_metadata in `extensions`.
+pub async fn request_metadata(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    extensions: &mut ExtensionRegistry,
+) -> Result<Vec<u8>> {
+    // NOTE: we only learn the total size from the first piece, so that one
+    // is requested on its own.
+    ExtensionMessage::MetadataRequest { piece: 0 }
+        .to_peer_message(extensions.peer_extensions())?
+        .send(stream)
+        .await?;
+
+    let mut pieces: Option<MetadataPieces> = None;
+
+    loop {
+        // Peers can send other messages in the meantime, so skip past them.
+        let message = PeerMessage::read(stream).await?;
+
+        if !matches!(message.id, PeerMessageId::Extension) {
+            continue;
+        }
+
+        let (piece, total_size, data) = match extensions.dispatch(stream, &message.payload).await? {
+            Some(ExtensionMessage::MetadataData {
+                piece,
+                total_size,
+                data,
+            }) => (piece, total_size, data),
+            Some(ExtensionMessage::MetadataReject { piece }) => {
+                anyhow::bail!("Peer rejected metadata piece {}", piece)
+            }
+            _ => continue,
+        };
+
+        let total_size = match usize::try_from(total_size) {
+            Ok(total_size) => total_size,
+            Err(_) => anyhow::bail!("Invalid metadata size: {}", total_size),
+        };
+
+        let pieces = match &mut pieces {
+            Some(pieces) => pieces,
+            None => {
+                let new_pieces = pieces.insert(MetadataPieces::new(total_size)?);
+
+                // Everything else can be asked for at once.
+                for piece in 1..new_pieces.count() as u32 {
+                    ExtensionMessage::MetadataRequest { piece }
+                        .to_peer_message(extensions.peer_extensions())?
+                        .send(stream)
+                        .await?;
+                }
+
+                new_pieces
+            }
+        };
+
+        anyhow::ensure!(
+            total_size == pieces.data.len(),
+            "Metadata size changed from {} to {}",
+            pieces.data.len(),
+            total_size
+        );
+
+        pieces.add(piece as usize, &data)?;
+
+        if pieces.is_complete() {
+            return Ok(std::mem::take(&mut pieces.data));
+        }
+    }
+}
+
+// Checks the metadata against every hash the magnet link has.
+pub fn verify_metadata(magnet_link: &MagnetLink, info: &[u8]) -> Result<()> {
+    if magnet_link.has_v1_hash() {
+        let hash = InfoHash::of(info);
+
+        anyhow::ensure!(
+            hash == magnet_link.hash,
+            "Metadata hash {} doesn't match {}",
+            hash,
+            magnet_link.hash
+        );
+    }
+
+    if let Some(expected) = magnet_link.hash_v2 {
+        let hash = InfoHashV2::of(info);
+
+        anyhow::ensure!(
+            hash == expected,
+            "Metadata hash {} doesn't match {}",
+            hash,
+            expected
+        );
+    }
+
+    Ok(())
+}
+
+fn placeholder_torrent(magnet_link: &MagnetLink) -> Torrent {
+    Torrent {
+        hash: magnet_link.hash,
+        hash_v2: magnet_link.hash_v2,
+        announce: magnet_link.tracker_url().to_string(),
+        announce_list: magnet_link.tracker_tiers(),
+        length: 999, // fake length to make the peer happy
+        ..Default::default()
+    }
+}
+
+struct MetadataPieces {
+    data: Vec<u8>,
+    received: Vec<bool>,
+}
+
+impl MetadataPieces {
+    fn new(total_size: usize) -> Result<Self> {
+        // NOTE: the metadata is an info dictionary, so it's held to the same
+        // limit as any other untrusted bencode.
+        let max_size = Limits::untrusted().max_size;
+
+        anyhow::ensure!(
+            total_size > 0 && total_size <= max_size,
+            "Invalid metadata size: {}",
+            total_size
+        );
+
+        Ok(Self {
+            data: vec![0; total_size],
+            received: vec![false; total_size.div_ceil(METADATA_PIECE_SIZE)],
+        })
+    }
+
+    fn count(&self) -> usize {
+        self.received.len()
+    }
+
+    fn add(&mut self, piece: usize, data: &[u8]) -> Result<()> {
+        anyhow::ensure!(piece < self.count(), "Invalid metadata piece: {}", piece);
+
+        let start = piece * METADATA_PIECE_SIZE;
+        let end = usize::min(start + METADATA_PIECE_SIZE, self.data.len());
+
+        anyhow::ensure!(
+            data.len() == end - start,
+            "Expected {} bytes in metadata piece {}, got {}",
+            end - start,
+            piece,
+            data.len()
+        );
+
+        self.data[start..end].copy_from_slice(data);
+        self.received[piece] = true;
+
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.received.iter().all(|received| *received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers::SupportedExtensions;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn handshake(hash: &InfoHash) -> Vec<u8> {
+        let mut handshake = vec![19];
+        handshake.extend_from_slice(b"BitTorrent protocol");
+        handshake.extend_from_slice(
+            &HandshakeReservedBytes::ExtensionsEnabled
+                .bits()
+                .to_be_bytes(),
+        );
+        handshake.extend_from_slice(hash.as_bytes());
+        handshake.extend_from_slice(b"00112233445566778899");
+        handshake
+    }
+
+    fn message(id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut message = (payload.len() as u32 + 1).to_be_bytes().to_vec();
+        message.push(id);
+        message.extend_from_slice(payload);
+        message
+    }
+
+    fn extension_handshake() -> Vec<u8> {
+        message(20, b"\0d1:md11:ut_metadatai1eee")
+    }
+
+    // A registry that has already had `extension_handshake` from the peer.
+    async fn extensions() -> ExtensionRegistry {
+        let mut extensions = ExtensionRegistry::new();
+        let mut stream = tokio_test::io::Builder::new().build();
+
+        extensions
+            .dispatch(&mut stream, &extension_handshake()[5..])
+            .await
+            .unwrap();
+
+        extensions
+    }
+
+    fn request(piece: usize) -> Vec<u8> {
+        message(
+            20,
+            format!("\x01d8:msg_typei0e5:piecei{}ee", piece).as_bytes(),
+        )
+    }
+
+    fn data(info: &[u8], piece: usize) -> Vec<u8> {
+        let mut payload = format!(
+            "\x01d8:msg_typei1e5:piecei{}e10:total_sizei{}ee",
+            piece,
+            info.len()
+        )
+        .into_bytes();
+        payload.extend(info.chunks(METADATA_PIECE_SIZE).nth(piece).unwrap());
+
+        message(20, &payload)
+    }
+
+    // An info dictionary big enough to need three metadata pieces.
+    fn large_info() -> Vec<u8> {
+        let pieces = vec![b'x'; 40_000];
+        let mut info = b"d6:lengthi1e4:name1:a12:piece lengthi1e6:pieces40000:".to_vec();
+        info.extend_from_slice(&pieces);
+        info.push(b'e');
+        info
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_from() {
+        let info = b"d6:lengthi1e4:name1:a12:piece lengthi1e6:pieces20:01234567890123456789e";
+        let magnet_link = MagnetLink {
+            hash: InfoHash::of(info),
+            ..Default::default()
+        };
+        let torrent = placeholder_torrent(&magnet_link);
+        let peer_id = PeerId(*b"abcdefghijklmnopqrst");
+
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&{
+                let mut ours = handshake(&magnet_link.hash);
+                ours[48..].copy_from_slice(peer_id.as_bytes());
+                ours
+            })
+            .read(&handshake(&magnet_link.hash))
+            .read(&message(5, &[0]))
+            .write(&extension_handshake())
+            .read(&extension_handshake())
+            .write(&request(0))
+            .read(&message(4, &[0, 0, 0, 0]))
+            .read(&data(info, 0))
+            .build();
+
+        let metadata = fetch_metadata_from(&mut stream, &torrent, &torrent.hash, &peer_id)
+            .await
+            .unwrap();
+
+        assert_eq!(info.to_vec(), metadata);
+        assert!(verify_metadata(&magnet_link, &metadata).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_request_metadata_reassembles_pieces() {
+        let info = large_info();
+
+        // Later pieces may come back in any order, and the peer's own request
+        // is rejected since we don't have the metadata yet.
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&request(0))
+            .read(&data(&info, 0))
+            .write(&request(1))
+            .write(&request(2))
+            .read(&request(0))
+            .write(&message(20, b"\x01d8:msg_typei2e5:piecei0ee"))
+            .read(&data(&info, 2))
+            .read(&data(&info, 1))
+            .build();
+
+        assert_eq!(
+            info,
+            request_metadata(&mut stream, &mut extensions().await)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_metadata_rejected() {
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&request(0))
+            .read(&message(20, b"\x01d8:msg_typei2e5:piecei0ee"))
+            .build();
+
+        let err = request_metadata(&mut stream, &mut extensions().await)
+            .await
+            .unwrap_err();
+
+        assert_eq!("Peer rejected metadata piece 0", err.to_string());
+    }
+
+    #[test]
+    fn test_verify_metadata() {
+        let info = b"d4:name1:ae";
+        let hash_v2 = InfoHashV2::of(info);
+
+        let v2_only = MagnetLink {
+            hash: hash_v2.truncated(),
+            hash_v2: Some(hash_v2),
+            ..Default::default()
+        };
+
+        assert!(verify_metadata(&v2_only, info).is_ok());
+        assert!(verify_metadata(&v2_only, b"d4:name1:be").is_err());
+
+        let hybrid = MagnetLink {
+            hash: InfoHash([0; 20]),
+            ..v2_only
+        };
+
+        assert!(verify_metadata(&hybrid, info).is_err());
+    }
+
+    #[test]
+    fn test_metadata_pieces() {
+        let mut pieces = MetadataPieces::new(METADATA_PIECE_SIZE + 10).unwrap();
+
+        assert_eq!(2, pieces.count());
+        assert!(pieces.add(1, &[1; 11]).is_err());
+        assert!(pieces.add(2, &[1; 10]).is_err());
+
+        pieces.add(1, &[1; 10]).unwrap();
+        assert!(!pieces.is_complete());
+
+        pieces.add(0, &[0; METADATA_PIECE_SIZE]).unwrap();
+        assert!(pieces.is_complete());
+        assert_eq!(&[1; 10], &pieces.data[METADATA_PIECE_SIZE..]);
+
+        assert!(MetadataPieces::new(0).is_err());
+        assert!(MetadataPieces::new(usize::MAX).is_err());
+    }
+
+    // Plays the part of a peer that sends `info` for any request.
+    async fn serve_metadata(listener: TcpListener, info: Vec<u8>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut their_handshake = [0; 68];
+        stream.read_exact(&mut their_handshake).await.unwrap();
+
+        let hash = InfoHash(their_handshake[28..48].try_into().unwrap());
+        stream.write_all(&handshake(&hash)).await.unwrap();
+        stream.write_all(&message(5, &[0])).await.unwrap();
+
+        loop {
+            let message = match PeerMessage::read(&mut stream).await {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+
+            if message.payload[0] == 0 {
+                stream.write_all(&extension_handshake()).await.unwrap();
+                continue;
+            }
+
+            if let ExtensionMessage::MetadataRequest { piece } =
+                ExtensionMessage::from_bytes(&message.payload, &SupportedExtensions::my_supported())
+                    .unwrap()
+            {
+                stream
+                    .write_all(&data(&info, piece as usize))
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_tries_other_peers() {
+        let info = large_info();
+        let magnet_link_hash = InfoHash::of(&info);
+
+        // The first peer sends metadata that doesn't match the hash.
+        let bad_peer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let good_peer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let magnet_link = MagnetLink {
+            hash: magnet_link_hash,
+            peers: vec![
+                bad_peer.local_addr().unwrap().to_string(),
+                good_peer.local_addr().unwrap().to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let mut bad_info = info.clone();
+        bad_info[10] = b'9';
+
+        tokio::spawn(serve_metadata(bad_peer, bad_info));
+        tokio::spawn(serve_metadata(good_peer, info.clone()));
+
+        let peer_id = PeerId(*b"abcdefghijklmnopqrst");
+
+        assert_eq!(info, fetch_metadata(&magnet_link, &peer_id).await.unwrap());
+    }
+}
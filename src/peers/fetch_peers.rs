@@ -1,15 +1,73 @@
-use crate::Torrent;
+use super::TrackerTiers;
+use crate::{
+    bencode::{self, DecodeOptions, Value as BValue},
+    InfoHash, PeerId, Torrent,
+};
 use anyhow::Result;
-use serde_bencode::value::Value as BValue;
 use std::{
+    fmt,
     io::Read,
     net::{Ipv4Addr, SocketAddrV4},
 };
 
-pub fn fetch_peers(torrent: &Torrent, peer_id: &str) -> Result<Vec<SocketAddrV4>> {
-    // This may look scary, but all it does is stick a '%' in between
-    // every pair of characters.
-    let info_hash = prepare_hash(&torrent.hash);
+// A peer, along with the swarm it was found in. Its handshake has to use
+// that swarm's info hash, which for hybrid torrents isn't always the torrent's `hash`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwarmPeer {
+    pub address: SocketAddrV4,
+    pub hash: InfoHash,
+}
+
+impl fmt::Display for SwarmPeer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.address)
+    }
+}
+
+// Tries every tracker in the torrent's announce-list, in tier order,
+// until one of them responds.
+pub fn fetch_peers(torrent: &Torrent, peer_id: &PeerId) -> Result<Vec<SwarmPeer>> {
+    TrackerTiers::from_torrent(torrent).fetch_peers(torrent, peer_id)
+}
+
+// NOTE: hybrid torrents are in two swarms, one per info hash, so we announce
+// to both and only fail if neither worked.
+pub fn fetch_peers_from(
+    announce: &str,
+    torrent: &Torrent,
+    peer_id: &PeerId,
+) -> Result<Vec<SwarmPeer>> {
+    let mut peers: Vec<SwarmPeer> = Vec::new();
+    let mut last_error = None;
+
+    for hash in torrent.swarm_hashes() {
+        match announce_hash(announce, &hash, torrent, peer_id) {
+            Ok(swarm_peers) => {
+                for address in swarm_peers {
+                    // Hybrid clients show up in both swarms.
+                    if !peers.iter().any(|peer| peer.address == address) {
+                        peers.push(SwarmPeer { address, hash });
+                    }
+                }
+            }
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    match last_error {
+        Some(err) if peers.is_empty() => Err(err),
+        _ => Ok(peers),
+    }
+}
+
+fn announce_hash(
+    announce: &str,
+    hash: &InfoHash,
+    torrent: &Torrent,
+    peer_id: &PeerId,
+) -> Result<Vec<SocketAddrV4>> {
+    let info_hash = url_encode_bytes(hash.as_bytes());
+    let peer_id = url_encode_bytes(peer_id.as_bytes());
 
     let port = 6881;
     let uploaded = 0;
@@ -19,9 +77,25 @@ pub fn fetch_peers(torrent: &Torrent, peer_id: &str) -> Result<Vec<SocketAddrV4>
     // NOTE: We have to manually build the URL like this because if we use reqwest's
     // query builder, it will try to encode the parameters, which breaks the info_hash
     // and peer_id parameters.
+    //
+    // Private trackers often have a passkey in the query already, so ours is
+    // added on to it.
+    let separator = match announce.contains('?') {
+        true => '&',
+        false => '?',
+    };
+
     let url = format!(
-        "{}?info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact={}",
-        torrent.announce, info_hash, peer_id, port, uploaded, downloaded, torrent.length, compact
+        "{}{}info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact={}",
+        announce,
+        separator,
+        info_hash,
+        peer_id,
+        port,
+        uploaded,
+        downloaded,
+        torrent.length,
+        compact
     );
 
     let mut response = reqwest::blocking::get(url)?;
@@ -33,7 +107,7 @@ pub fn fetch_peers(torrent: &Torrent, peer_id: &str) -> Result<Vec<SocketAddrV4>
     let mut body = Vec::<u8>::new();
     response.read_to_end(&mut body)?;
 
-    let body = match serde_bencode::from_bytes::<BValue>(&body) {
+    let body = match bencode::decode_with_options(&body, DecodeOptions::untrusted()) {
         Ok(BValue::Dict(body)) => body,
         _ => anyhow::bail!("Response body is not a bencoded dictionary"),
     };
@@ -43,8 +117,15 @@ pub fn fetch_peers(torrent: &Torrent, peer_id: &str) -> Result<Vec<SocketAddrV4>
         _ => anyhow::bail!("No peers in response"),
     };
 
+    // Compact peers are 4 bytes of IPv4 address and 2 of port each.
+    anyhow::ensure!(
+        peers.len() % 6 == 0,
+        "Invalid compact peers length: {}",
+        peers.len()
+    );
+
     Ok(peers
-        .chunks(6)
+        .chunks_exact(6)
         .map(|chunk| {
             let mut address = [0u8; 4];
             address.clone_from_slice(&chunk[0..4]);
@@ -58,23 +139,15 @@ pub fn fetch_peers(torrent: &Torrent, peer_id: &str) -> Result<Vec<SocketAddrV4>
 }
 
 // NOTE: this could be made slightly more efficient if we only encoded
-// the characters that _need_ to be encoded. Right now, it encodes
-// every pair of characters by default.
-fn prepare_hash(hash: &str) -> String {
-    hash.chars()
-        .enumerate()
-        .flat_map(|(i, c)| {
-            if i % 2 == 0 { Some('%') } else { None }
-                .into_iter()
-                .chain(std::iter::once(c))
-        })
-        .collect::<String>()
+// the bytes that _need_ to be encoded. Right now, it encodes every byte.
+fn url_encode_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("%{:02x}", byte)).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_fetch_peers() {
@@ -83,9 +156,10 @@ mod tests {
         let torrent = Torrent {
             announce: format!("{}/announce", server.url()),
             length: 1337,
-            hash: "abcd1234".to_string(),
+            hash: InfoHash(*b"abcd1234abcd1234abcd"),
             piece_length: 0,
             piece_hashes: Vec::<String>::new(),
+            ..Default::default()
         };
 
         let expected_peers = vec![
@@ -105,16 +179,16 @@ mod tests {
             })
             .collect::<Vec<u8>>();
 
-        let mut response_dict = HashMap::new();
+        let mut response_dict = BTreeMap::new();
         response_dict.insert(
             "peers".as_bytes().to_vec(),
             BValue::Bytes(encoded_expected_peers),
         );
 
-        let response_body = serde_bencode::to_bytes(&BValue::Dict(response_dict)).unwrap();
+        let response_body = bencode::encode(&BValue::Dict(response_dict));
 
-        let info_hash = prepare_hash(&torrent.hash);
-        let peer_id = "00112233445566778899";
+        let info_hash = url_encode_bytes(torrent.hash.as_bytes());
+        let peer_id = PeerId(*b"00112233445566778899");
         let port = 6881;
         let uploaded = 0;
         let downloaded = 0;
@@ -125,7 +199,7 @@ mod tests {
         let url = format!(
             "/announce?info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact={}",
             info_hash,
-            peer_id,
+            url_encode_bytes(peer_id.as_bytes()),
             port,
             uploaded,
             downloaded,
@@ -138,9 +212,126 @@ mod tests {
             .with_body(response_body)
             .create();
 
-        let actual_peers = fetch_peers(&torrent, peer_id).unwrap();
+        let actual_peers = fetch_peers(&torrent, &peer_id).unwrap();
+
+        mock.assert();
+        assert_eq!(
+            expected_peers,
+            actual_peers
+                .iter()
+                .map(|peer| peer.address)
+                .collect::<Vec<_>>()
+        );
+        assert!(actual_peers.iter().all(|peer| peer.hash == torrent.hash));
+    }
+
+    #[test]
+    fn test_fetch_peers_from_hybrid_swarms() {
+        let mut server = mockito::Server::new();
+
+        let hash_v2 = crate::InfoHashV2([7; 32]);
+        let torrent = Torrent {
+            announce: format!("{}/announce", server.url()),
+            hash: InfoHash([0xab; 20]),
+            hash_v2: Some(hash_v2),
+            ..Default::default()
+        };
+
+        let both = SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 5);
+        let v2_only = SocketAddrV4::new(Ipv4Addr::new(5, 6, 7, 8), 9);
+
+        let response = |peers: &[SocketAddrV4]| {
+            let peers = peers
+                .iter()
+                .flat_map(|peer| {
+                    let mut bytes = peer.ip().octets().to_vec();
+                    bytes.extend_from_slice(&peer.port().to_be_bytes());
+                    bytes
+                })
+                .collect();
+
+            bencode::encode(&BValue::Dict(BTreeMap::from([(
+                b"peers".to_vec(),
+                BValue::Bytes(peers),
+            )])))
+        };
+
+        for (hash, peers) in [
+            (torrent.hash, vec![both]),
+            (hash_v2.truncated(), vec![both, v2_only]),
+        ] {
+            server
+                .mock("GET", mockito::Matcher::Any)
+                .match_query(mockito::Matcher::Regex(format!(
+                    "info_hash={}",
+                    url_encode_bytes(hash.as_bytes())
+                )))
+                .with_body(response(&peers))
+                .create();
+        }
+
+        let peers = fetch_peers(&torrent, &PeerId(*b"00112233445566778899")).unwrap();
+
+        assert_eq!(
+            vec![
+                SwarmPeer {
+                    address: both,
+                    hash: torrent.hash,
+                },
+                SwarmPeer {
+                    address: v2_only,
+                    hash: hash_v2.truncated(),
+                },
+            ],
+            peers
+        );
+    }
+
+    #[test]
+    fn test_fetch_peers_from_with_passkey() {
+        let mut server = mockito::Server::new();
+
+        let mock = server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Regex(
+                "^passkey=secret&info_hash=".to_string(),
+            ))
+            .with_body(bencode::encode(&BValue::Dict(BTreeMap::from([(
+                b"peers".to_vec(),
+                BValue::Bytes(vec![1, 2, 3, 4, 0, 5]),
+            )]))))
+            .create();
+
+        let peers = fetch_peers_from(
+            &format!("{}/announce?passkey=secret", server.url()),
+            &Torrent::default(),
+            &PeerId(*b"00112233445566778899"),
+        )
+        .unwrap();
 
         mock.assert();
-        assert_eq!(expected_peers, actual_peers);
+        assert_eq!(1, peers.len());
+    }
+
+    #[test]
+    fn test_fetch_peers_from_bad_compact_peers() {
+        let mut server = mockito::Server::new();
+
+        server
+            .mock("GET", mockito::Matcher::Any)
+            .with_body(bencode::encode(&BValue::Dict(BTreeMap::from([(
+                b"peers".to_vec(),
+                BValue::Bytes(vec![1, 2, 3, 4, 0]),
+            )]))))
+            .create();
+
+        let err = fetch_peers_from(
+            &format!("{}/announce", server.url()),
+            &Torrent::default(),
+            &PeerId(*b"00112233445566778899"),
+        )
+        .unwrap_err();
+
+        assert_eq!("Invalid compact peers length: 5", err.to_string());
     }
 }
@@ -1,5 +1,5 @@
-use rand::distributions::{Alphanumeric, DistString};
+use crate::PeerId;
 
-pub fn generate_peer_id() -> String {
-    Alphanumeric.sample_string(&mut rand::thread_rng(), 20)
+pub fn generate_peer_id() -> PeerId {
+    PeerId::random()
 }
@@ -1,4 +1,4 @@
-use crate::FileInfo;
+use crate::{bencode::Limits, FileInfo};
 use anyhow::Result;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use std::fmt;
@@ -32,6 +32,15 @@ impl PeerMessage {
             return Ok(Self::keep_alive());
         }
 
+        // NOTE: the length comes straight from the peer. The largest thing we
+        // accept is an extension message, which is bencode within the
+        // untrusted limits plus the message and extension ids.
+        anyhow::ensure!(
+            length <= Limits::untrusted().max_size + 2,
+            "Message is too large: {} bytes",
+            length
+        );
+
         let id: PeerMessageId = stream.read_u8().await?.try_into()?;
 
         let mut payload = vec![0_u8; length - 1];
@@ -69,6 +78,7 @@ impl PeerMessage {
 
                         piece
                             .block_details()
+                            .filter(|(block_index, _)| !piece.has_block(*block_index as usize))
                             .map(move |(block_index, block_size)| {
                                 (piece_index, block_index, block_size)
                             })
@@ -118,6 +128,8 @@ pub enum PeerMessageId {
     Request = 6,
     Piece = 7,
     Cancel = 8,
+    // BEP 5, sent by peers that support DHT, often before anything else.
+    Port = 9,
     Extension = 20,
     KeepAlive,
 }
@@ -0,0 +1,146 @@
+use super::{
+    shake_hands, ExtensionMessage, ExtensionRegistry, HandshakeReservedBytes, PeerMessage,
+    PeerMessageId, METADATA_PIECE_SIZE,
+};
+use crate::{PeerId, Torrent};
+use anyhow::Result;
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+// Lets a peer that connected to us fetch the torrent's info dictionary over
+// ut_metadata (BEP 9). Runs until the peer hangs up.
+pub async fn serve_metadata(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    torrent: &Torrent,
+    peer_id: &PeerId,
+) -> Result<()> {
+    shake_hands(
+        stream,
+        torrent,
+        peer_id,
+        HandshakeReservedBytes::ExtensionsEnabled,
+    )
+    .await?;
+
+    // We're only here to hand out metadata, so we don't have any pieces.
+    PeerMessage {
+        id: PeerMessageId::Bitfield,
+        payload: vec![0; torrent.piece_hashes.len().div_ceil(8)],
+    }
+    .send(stream)
+    .await?;
+
+    let mut extensions = ExtensionRegistry::with_metadata(torrent.info_bytes.clone());
+    extensions.handshake()?.send(stream).await?;
+
+    loop {
+        let message = match PeerMessage::read(stream).await {
+            Ok(message) => message,
+            Err(err) if is_eof(&err) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        if !matches!(message.id, PeerMessageId::Extension) {
+            continue;
+        }
+
+        extensions.dispatch(stream, &message.payload).await?;
+    }
+}
+
+// The reply to a ut_metadata request: the piece if we have it, or a reject
+// if we don't. An empty `info` means we don't have the metadata at all.
+pub fn metadata_response(info: &[u8], piece: u32) -> ExtensionMessage {
+    let start = (piece as usize)
+        .checked_mul(METADATA_PIECE_SIZE)
+        .filter(|start| *start < info.len());
+
+    match start {
+        Some(start) => {
+            let end = usize::min(start + METADATA_PIECE_SIZE, info.len());
+
+            ExtensionMessage::MetadataData {
+                piece,
+                total_size: info.len() as u64,
+                data: info[start..end].to_vec(),
+            }
+        }
+        None => ExtensionMessage::MetadataReject { piece },
+    }
+}
+
+fn is_eof(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{peers::fetch_metadata_from, InfoHash};
+
+    #[test]
+    fn test_metadata_response() {
+        let info = vec![b'x'; METADATA_PIECE_SIZE + 10];
+
+        assert_eq!(
+            ExtensionMessage::MetadataData {
+                piece: 1,
+                total_size: info.len() as u64,
+                data: vec![b'x'; 10],
+            },
+            metadata_response(&info, 1)
+        );
+        assert_eq!(
+            ExtensionMessage::MetadataReject { piece: 2 },
+            metadata_response(&info, 2)
+        );
+        assert_eq!(
+            ExtensionMessage::MetadataReject { piece: 0 },
+            metadata_response(&[], 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_metadata() {
+        let mut info = b"d6:lengthi1e4:name1:a12:piece lengthi1e6:pieces40000:".to_vec();
+        info.extend_from_slice(&[b'x'; 40_000]);
+        info.push(b'e');
+
+        let torrent = Torrent {
+            hash: InfoHash::of(&info),
+            piece_hashes: vec![String::new(); 2000],
+            info_bytes: info.clone(),
+            ..Default::default()
+        };
+
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+
+        let server_torrent = torrent.clone();
+        let server = tokio::spawn(async move {
+            serve_metadata(
+                &mut server,
+                &server_torrent,
+                &PeerId(*b"serverserverserverse"),
+            )
+            .await
+        });
+
+        let metadata = fetch_metadata_from(
+            &mut client,
+            &Torrent {
+                hash: torrent.hash,
+                ..Default::default()
+            },
+            &torrent.hash,
+            &PeerId(*b"clientclientclientcl"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(info, metadata);
+
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use crate::Torrent;
+use crate::{InfoHash, PeerId, Torrent};
 use anyhow::Result;
 use bitflags::bitflags;
 use core::fmt;
@@ -8,9 +8,28 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 pub async fn shake_hands(
     stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
     torrent: &Torrent,
-    peer_id: &str,
+    peer_id: &PeerId,
     reserved_bytes: HandshakeReservedBytes,
 ) -> Result<HandshakeResponse> {
+    shake_hands_in_swarm(stream, torrent, &torrent.hash, peer_id, reserved_bytes).await
+}
+
+// NOTE: a hybrid torrent is in two swarms, and peers from its v2 swarm only
+// know it by the truncated v2 hash. `hash` should be the one for the swarm
+// the peer came from, see `SwarmPeer`.
+pub async fn shake_hands_in_swarm(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    torrent: &Torrent,
+    hash: &InfoHash,
+    peer_id: &PeerId,
+    reserved_bytes: HandshakeReservedBytes,
+) -> Result<HandshakeResponse> {
+    anyhow::ensure!(
+        torrent.swarm_hashes().contains(hash),
+        "{} isn't one of the torrent's info hashes",
+        hash
+    );
+
     let mut handshake = vec![u8::to_be(19)];
 
     // Standard header
@@ -20,8 +39,7 @@ pub async fn shake_hands(
     handshake.extend_from_slice(&reserved_bytes.bits().to_be_bytes());
 
     // Hash
-    let hash = hex::decode(&torrent.hash)?;
-    handshake.extend_from_slice(&hash);
+    handshake.extend_from_slice(hash.as_bytes());
 
     // Peer ID
     handshake.extend_from_slice(peer_id.as_bytes());
@@ -37,12 +55,21 @@ pub async fn shake_hands(
     let protocol = String::from_utf8_lossy(&buffer[1..20]);
     anyhow::ensure!(protocol == "BitTorrent protocol", "Invalid protocol");
 
-    let encoded_peer_id = hex::encode(&buffer[48..68]);
+    // NOTE: peers that connect to us may come from either swarm of a hybrid
+    // torrent, so either hash is fine. Anything else is another torrent.
+    let peer_hash = InfoHash(buffer[28..48].try_into()?);
+    anyhow::ensure!(
+        torrent.swarm_hashes().contains(&peer_hash),
+        "Peer wants a different torrent: {}",
+        peer_hash
+    );
+
+    let peer_id = PeerId(buffer[48..68].try_into()?);
     let reserved_bytes =
         HandshakeReservedBytes::from_bits_truncate(u64::from_be_bytes(buffer[20..28].try_into()?));
 
     Ok(HandshakeResponse {
-        encoded_peer_id,
+        peer_id,
         reserved_bytes,
     })
 }
@@ -63,22 +90,24 @@ impl fmt::Display for HandshakeReservedBytes {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct HandshakeResponse {
-    pub encoded_peer_id: String,
+    pub peer_id: PeerId,
     pub reserved_bytes: HandshakeReservedBytes,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::InfoHash;
 
     #[tokio::test]
     async fn test_shake_hands() {
         let torrent = Torrent {
             announce: "fake-url/announce".to_string(),
             length: 1337,
-            hash: hex::encode("12345678901234567890"),
+            hash: InfoHash(*b"12345678901234567890"),
             piece_length: 0,
             piece_hashes: Vec::<String>::new(),
+            ..Default::default()
         };
 
         let mut handshake = Vec::<u8>::new();
@@ -91,15 +120,14 @@ mod tests {
         handshake.extend_from_slice(&[0_u8; 8]);
 
         // Hash
-        let hash = hex::decode(&torrent.hash).unwrap();
-        handshake.extend_from_slice(&hash);
+        handshake.extend_from_slice(torrent.hash.as_bytes());
 
         // Peer ID
-        let peer_id = "00112233445566778899";
+        let peer_id = PeerId(*b"00112233445566778899");
         handshake.extend_from_slice(peer_id.as_bytes());
 
         let expected_response = HandshakeResponse {
-            encoded_peer_id: hex::encode(peer_id),
+            peer_id,
             reserved_bytes: HandshakeReservedBytes::empty(),
         };
 
@@ -111,7 +139,7 @@ mod tests {
         let actual_response = shake_hands(
             &mut stream,
             &torrent,
-            peer_id,
+            &peer_id,
             HandshakeReservedBytes::empty(),
         )
         .await
@@ -125,9 +153,10 @@ mod tests {
         let torrent = Torrent {
             announce: "fake-url/announce".to_string(),
             length: 1337,
-            hash: hex::encode("12345678901234567890"),
+            hash: InfoHash(*b"12345678901234567890"),
             piece_length: 0,
             piece_hashes: Vec::<String>::new(),
+            ..Default::default()
         };
 
         let mut handshake = Vec::<u8>::new();
@@ -144,15 +173,14 @@ mod tests {
         );
 
         // Hash
-        let hash = hex::decode(&torrent.hash).unwrap();
-        handshake.extend_from_slice(&hash);
+        handshake.extend_from_slice(torrent.hash.as_bytes());
 
         // Peer ID
-        let peer_id = "00112233445566778899";
+        let peer_id = PeerId(*b"00112233445566778899");
         handshake.extend_from_slice(peer_id.as_bytes());
 
         let expected_response = HandshakeResponse {
-            encoded_peer_id: hex::encode(peer_id),
+            peer_id,
             reserved_bytes: HandshakeReservedBytes::ExtensionsEnabled,
         };
 
@@ -164,7 +192,7 @@ mod tests {
         let actual_response = shake_hands(
             &mut stream,
             &torrent,
-            peer_id,
+            &peer_id,
             HandshakeReservedBytes::ExtensionsEnabled,
         )
         .await
@@ -172,4 +200,77 @@ mod tests {
 
         assert_eq!(expected_response, actual_response);
     }
+
+    #[tokio::test]
+    async fn test_shake_hands_in_swarm() {
+        let hash_v2 = crate::InfoHashV2([7; 32]);
+        let torrent = Torrent {
+            hash: InfoHash(*b"12345678901234567890"),
+            hash_v2: Some(hash_v2),
+            ..Default::default()
+        };
+
+        let mut handshake = vec![19];
+        handshake.extend_from_slice(b"BitTorrent protocol");
+        handshake.extend_from_slice(&[0_u8; 8]);
+        handshake.extend_from_slice(hash_v2.truncated().as_bytes());
+
+        let peer_id = PeerId(*b"00112233445566778899");
+        handshake.extend_from_slice(peer_id.as_bytes());
+
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&handshake)
+            .read(&handshake)
+            .build();
+
+        let response = shake_hands_in_swarm(
+            &mut stream,
+            &torrent,
+            &hash_v2.truncated(),
+            &peer_id,
+            HandshakeReservedBytes::empty(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(peer_id, response.peer_id);
+    }
+
+    #[tokio::test]
+    async fn test_shake_hands_rejects_other_torrents() {
+        let torrent = Torrent {
+            hash: InfoHash(*b"12345678901234567890"),
+            ..Default::default()
+        };
+
+        let peer_id = PeerId(*b"00112233445566778899");
+
+        let handshake = |hash: &InfoHash| {
+            let mut handshake = vec![19];
+            handshake.extend_from_slice(b"BitTorrent protocol");
+            handshake.extend_from_slice(&[0_u8; 8]);
+            handshake.extend_from_slice(hash.as_bytes());
+            handshake.extend_from_slice(peer_id.as_bytes());
+            handshake
+        };
+
+        let mut stream = tokio_test::io::Builder::new()
+            .write(&handshake(&torrent.hash))
+            .read(&handshake(&InfoHash([0; 20])))
+            .build();
+
+        let err = shake_hands(
+            &mut stream,
+            &torrent,
+            &peer_id,
+            HandshakeReservedBytes::empty(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            format!("Peer wants a different torrent: {}", InfoHash([0; 20])),
+            err.to_string()
+        );
+    }
 }
@@ -0,0 +1,168 @@
+use super::{fetch_peers_from, SwarmPeer};
+use crate::{PeerId, Torrent};
+use anyhow::Result;
+use rand::seq::SliceRandom;
+
+// NOTE: this follows BEP 12. Trackers within a tier are shuffled once up
+// front, then tried in order, moving on to the next tier only when every
+// tracker in the current one has failed. Whichever tracker responds gets
+// moved to the front of its tier, so it's tried first next time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackerTiers {
+    tiers: Vec<Vec<String>>,
+}
+
+impl TrackerTiers {
+    pub fn new(tiers: Vec<Vec<String>>) -> Self {
+        Self { tiers }
+    }
+
+    pub fn from_torrent(torrent: &Torrent) -> Self {
+        let mut tiers = torrent.tracker_tiers();
+        let mut rng = rand::thread_rng();
+
+        for tier in tiers.iter_mut() {
+            tier.shuffle(&mut rng);
+        }
+
+        Self::new(tiers)
+    }
+
+    pub fn tiers(&self) -> &[Vec<String>] {
+        &self.tiers
+    }
+
+    pub fn fetch_peers(&mut self, torrent: &Torrent, peer_id: &PeerId) -> Result<Vec<SwarmPeer>> {
+        let mut last_error = None;
+
+        for tier in self.tiers.iter_mut() {
+            for i in 0..tier.len() {
+                match fetch_peers_from(&tier[i], torrent, peer_id) {
+                    Ok(peers) => {
+                        let tracker = tier.remove(i);
+                        tier.insert(0, tracker);
+
+                        return Ok(peers);
+                    }
+                    Err(err) => last_error = Some(err.context(format!("{} failed", tier[i]))),
+                }
+            }
+        }
+
+        match last_error {
+            Some(err) => Err(err),
+            None => anyhow::bail!("No trackers to announce to"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bencode::{self, Value},
+        InfoHash,
+    };
+    use std::{
+        collections::BTreeMap,
+        net::{Ipv4Addr, SocketAddrV4},
+    };
+
+    fn peers_response() -> Vec<u8> {
+        let response_dict = BTreeMap::from([(
+            b"peers".to_vec(),
+            Value::Bytes(vec![161, 35, 46, 221, 0xc8, 0xd6]),
+        )]);
+
+        bencode::encode(&Value::Dict(response_dict))
+    }
+
+    #[test]
+    fn test_fetch_peers_promotes_working_tracker() {
+        let mut server = mockito::Server::new();
+
+        let failing = server
+            .mock("GET", mockito::Matcher::Regex("^/failing".to_string()))
+            .with_status(500)
+            .create();
+        let working = server
+            .mock("GET", mockito::Matcher::Regex("^/working".to_string()))
+            .with_body(peers_response())
+            .create();
+
+        let failing_url = format!("{}/failing", server.url());
+        let working_url = format!("{}/working", server.url());
+
+        let torrent = Torrent {
+            length: 1337,
+            hash: InfoHash([0xab; 20]),
+            ..Default::default()
+        };
+
+        let mut tiers = TrackerTiers::new(vec![vec![failing_url.clone(), working_url.clone()]]);
+
+        let actual_peers = tiers
+            .fetch_peers(&torrent, &PeerId(*b"00112233445566778899"))
+            .unwrap();
+
+        failing.assert();
+        working.assert();
+        assert_eq!(
+            vec![SwarmPeer {
+                address: SocketAddrV4::new(Ipv4Addr::new(161, 35, 46, 221), 51414),
+                hash: torrent.hash,
+            }],
+            actual_peers
+        );
+        assert_eq!(&[vec![working_url, failing_url]], tiers.tiers());
+    }
+
+    #[test]
+    fn test_fetch_peers_falls_back_to_next_tier() {
+        let mut server = mockito::Server::new();
+
+        server
+            .mock("GET", mockito::Matcher::Regex("^/failing".to_string()))
+            .with_status(500)
+            .create();
+        let backup = server
+            .mock("GET", mockito::Matcher::Regex("^/backup".to_string()))
+            .with_body(peers_response())
+            .create();
+
+        let torrent = Torrent {
+            length: 1337,
+            hash: InfoHash([0xab; 20]),
+            ..Default::default()
+        };
+
+        let mut tiers = TrackerTiers::new(vec![
+            vec![format!("{}/failing", server.url())],
+            vec![format!("{}/backup", server.url())],
+        ]);
+
+        assert!(tiers
+            .fetch_peers(&torrent, &PeerId(*b"00112233445566778899"))
+            .is_ok());
+        backup.assert();
+    }
+
+    #[test]
+    fn test_fetch_peers_reports_last_error() {
+        let mut server = mockito::Server::new();
+
+        server
+            .mock("GET", mockito::Matcher::Any)
+            .with_status(500)
+            .create();
+
+        let torrent = Torrent::default();
+        let mut tiers = TrackerTiers::new(vec![vec![format!("{}/failing", server.url())]]);
+
+        let err = tiers
+            .fetch_peers(&torrent, &PeerId(*b"00112233445566778899"))
+            .unwrap_err();
+
+        assert!(err.to_string().contains("/failing failed"));
+    }
+}
@@ -0,0 +1,62 @@
+use super::{metadata_response, Extension, ExtensionMessage};
+use anyhow::Result;
+
+pub const UT_METADATA: &str = "ut_metadata";
+
+// ut_metadata (BEP 9), which every `ExtensionRegistry` starts out with.
+// Answers the peer's requests out of `info`, and rejects them all if that's
+// empty. Data and rejects for our own requests are left to whoever called
+// `ExtensionRegistry::dispatch`.
+#[derive(Clone, Debug, Default)]
+pub struct UtMetadata {
+    info: Vec<u8>,
+}
+
+impl UtMetadata {
+    pub fn new(info: Vec<u8>) -> Self {
+        Self { info }
+    }
+}
+
+impl Extension for UtMetadata {
+    fn name(&self) -> &str {
+        UT_METADATA
+    }
+
+    fn on_message(&mut self, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+        match ExtensionMessage::metadata_from_bytes(payload)? {
+            ExtensionMessage::MetadataRequest { piece } => {
+                Ok(vec![metadata_response(&self.info, piece).payload()?])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_on_message() {
+        let mut ut_metadata = UtMetadata::new(b"d4:name1:ae".to_vec());
+
+        assert_eq!(
+            vec![b"d8:msg_typei1e5:piecei0e10:total_sizei11eed4:name1:ae".to_vec()],
+            ut_metadata
+                .on_message(b"d8:msg_typei0e5:piecei0ee")
+                .unwrap()
+        );
+        assert_eq!(
+            vec![b"d8:msg_typei2e5:piecei1ee".to_vec()],
+            ut_metadata
+                .on_message(b"d8:msg_typei0e5:piecei1ee")
+                .unwrap()
+        );
+        assert!(ut_metadata
+            .on_message(b"d8:msg_typei2e5:piecei0ee")
+            .unwrap()
+            .is_empty());
+        assert!(ut_metadata.on_message(b"not bencode").is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::hash::calculate_hash;
 use anyhow::Result;
-use std::iter::from_fn;
+use std::{iter::from_fn, ops::Range};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 const BLOCK_SIZE: usize = 16384; // 16 * 1024
@@ -30,6 +30,18 @@ impl Piece {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn block_details(&self) -> impl Iterator<Item = (u32, u32)> {
         // NOTE: We copy the length here to avoid borrowing self in the closure.
         let length = self.data.len();
@@ -57,6 +69,26 @@ impl Piece {
         self.completed[completed_index] = true;
     }
 
+    // NOTE: used for BEP 47 padding, which is always zeros. Any block that
+    // is entirely padding is marked as done, so it never gets requested.
+    pub fn fill_zeros(&mut self, range: Range<usize>) {
+        self.data[range.clone()].fill(0);
+
+        for block in range.start.div_ceil(BLOCK_SIZE)..self.completed.len() {
+            let block_end = usize::min((block + 1) * BLOCK_SIZE, self.data.len());
+
+            if block_end > range.end {
+                break;
+            }
+
+            self.completed[block] = true;
+        }
+    }
+
+    pub fn has_block(&self, index: usize) -> bool {
+        self.completed[index / BLOCK_SIZE]
+    }
+
     pub fn is_complete(&self) -> bool {
         self.completed.iter().all(|b| *b)
     }
@@ -151,6 +183,28 @@ mod tests {
         assert_eq!(expected_piece, actual_piece);
     }
 
+    #[test]
+    fn test_fill_zeros() {
+        let length = BLOCK_SIZE * 2 + BLOCK_SIZE / 2;
+        let hash = "00112233445566778899".to_string();
+        let mut piece = Piece::new(length, &hash);
+
+        piece.update_block(0, vec![1_u8; BLOCK_SIZE]);
+        piece.fill_zeros(BLOCK_SIZE / 2..length);
+
+        assert_eq!(vec![true, true, true], piece.completed);
+        assert!(piece.data()[..BLOCK_SIZE / 2].iter().all(|b| *b == 1));
+        assert!(piece.data()[BLOCK_SIZE / 2..].iter().all(|b| *b == 0));
+
+        // A block that's only partly padding still has to be downloaded.
+        let mut piece = Piece::new(length, &hash);
+        piece.fill_zeros(BLOCK_SIZE / 2..BLOCK_SIZE * 2 + 1);
+
+        assert_eq!(vec![false, true, false], piece.completed);
+        assert!(!piece.has_block(0));
+        assert!(piece.has_block(BLOCK_SIZE));
+    }
+
     #[test]
     fn test_is_complete() {
         let length = BLOCK_SIZE * 2 + BLOCK_SIZE / 2;
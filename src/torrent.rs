@@ -1,69 +1,347 @@
-use crate::calculate_hash;
-use serde_bencode::value::Value as BValue;
-use std::fs;
+use crate::{
+    bencode::{self, DecodeOptions, Value},
+    InfoHash, InfoHashV2, MagnetLink,
+};
+use std::collections::BTreeMap;
+
+mod error;
+pub use error::TorrentError;
+
+mod builder;
+pub use builder::TorrentBuilder;
+pub use builder::TorrentVersion;
+
+mod merkle;
+pub use merkle::piece_layer;
+pub use merkle::pieces_root;
+pub use merkle::verify_piece_layer;
+pub use merkle::verify_piece_v2;
+pub use merkle::MerkleHash;
+
+mod validate;
+pub use validate::validate_torrent;
+pub use validate::Severity;
+pub use validate::ValidationIssue;
+pub use validate::ValidationReport;
+
+mod metainfo;
+pub use metainfo::FileTreeEntry;
+pub use metainfo::Info;
+pub use metainfo::Metainfo;
 
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Torrent {
     pub announce: String,
+    // BEP 12 tiers of backup trackers. Empty if the torrent only has `announce`.
+    pub announce_list: Vec<Vec<String>>,
     pub length: i64,
-    pub hash: String,
+    // The info hash to announce and handshake with: SHA-1 for v1 and hybrid
+    // torrents, and the truncated SHA-256 hash for v2-only ones.
+    pub hash: InfoHash,
+    // The full SHA-256 info hash of v2 and hybrid torrents.
+    pub hash_v2: Option<InfoHashV2>,
     pub piece_length: i64,
     pub piece_hashes: Vec<String>,
+    pub name: String,
+    // Empty for single-file torrents, which only have a `length`.
+    pub files: Vec<TorrentFile>,
+    pub comment: Option<String>,
+    // The info dictionary exactly as we read it, so writing the torrent back
+    // out keeps the same info hash.
+    pub info_bytes: Vec<u8>,
+    // Every other top-level entry, already encoded.
+    pub extra: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TorrentFile {
+    pub length: i64,
+    pub path: Vec<String>,
+    // BEP 47 attributes, one character each: `p` for padding, `x` for
+    // executable, `h` for hidden and `l` for symlinks.
+    pub attr: String,
+    // Where a symlink points, relative to the torrent's root.
+    pub symlink_path: Option<Vec<String>>,
+}
+
+impl TorrentFile {
+    pub fn is_padding(&self) -> bool {
+        self.attr.contains('p')
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.attr.contains('x')
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.attr.contains('h')
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.attr.contains('l')
+    }
 }
 
 impl Torrent {
-    pub fn from_file(path: &str) -> Result<Self, serde_bencode::Error> {
-        let contents = fs::read(path).expect("Failed to read file.");
+    pub fn from_file(path: &str) -> Result<Self, TorrentError> {
+        Ok(Metainfo::from_file(path)?.into())
+    }
 
-        Self::from_bytes(&contents)
+    pub fn from_bytes(contents: &[u8]) -> Result<Self, TorrentError> {
+        Ok(Metainfo::from_bytes(contents)?.into())
     }
 
-    pub fn from_bytes(contents: &[u8]) -> Result<Self, serde_bencode::Error> {
-        let dict = serde_bencode::from_bytes::<BValue>(contents);
+    // The trackers to use, in tier order. Falls back on `announce` when
+    // there's no announce-list, as BEP 12 describes. Empty for trackerless
+    // torrents.
+    pub fn tracker_tiers(&self) -> Vec<Vec<String>> {
+        match (self.announce_list.is_empty(), self.announce.is_empty()) {
+            (false, _) => self.announce_list.clone(),
+            (true, false) => vec![vec![self.announce.clone()]],
+            (true, true) => Vec::new(),
+        }
+    }
 
-        let data = match dict {
-            Ok(BValue::Dict(dict)) => dict,
-            _ => panic!("Invalid torrent file does not represent a bencoded dictionary."),
-        };
+    // Writes the torrent back out. Only the trackers and comment can change,
+    // everything else is kept as it was.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries = self.extra.clone();
 
-        let announce = match data.get("announce".as_bytes()) {
-            Some(BValue::Bytes(url)) => String::from_utf8_lossy(url).to_string(),
-            _ => panic!("Torrent file does not contain announce entry."),
-        };
+        if !self.announce.is_empty() {
+            entries.insert(
+                b"announce".to_vec(),
+                bencode::encode(&self.announce.as_str().into()),
+            );
+        }
 
-        let info = match data.get("info".as_bytes()) {
-            Some(BValue::Dict(info)) => info,
-            _ => panic!("Torrent file does not contain info entry."),
-        };
+        if !self.announce_list.is_empty() {
+            let tiers = self
+                .announce_list
+                .iter()
+                .map(|tier| Value::List(tier.iter().map(|url| url.as_str().into()).collect()))
+                .collect();
 
-        let length = match info.get("length".as_bytes()) {
-            Some(BValue::Int(len)) => *len,
-            _ => panic!("Torrent file does not contain length entry."),
-        };
+            entries.insert(
+                b"announce-list".to_vec(),
+                bencode::encode(&Value::List(tiers)),
+            );
+        }
 
-        let piece_length = match info.get("piece length".as_bytes()) {
-            Some(BValue::Int(len)) => *len,
-            _ => panic!("Torrent file does not contain piece length entry."),
-        };
+        if let Some(comment) = &self.comment {
+            entries.insert(
+                b"comment".to_vec(),
+                bencode::encode(&comment.as_str().into()),
+            );
+        }
+
+        entries.insert(b"info".to_vec(), self.info_bytes.clone());
+
+        // NOTE: we can't go through `Value` here, since the entries are
+        // already encoded, but the format is simple enough to write directly.
+        let mut output = vec![b'd'];
+
+        for (key, value) in entries {
+            output.extend_from_slice(format!("{}:", key.len()).as_bytes());
+            output.extend_from_slice(&key);
+            output.extend_from_slice(&value);
+        }
+
+        output.push(b'e');
+        output
+    }
+
+    // Adds a tracker in a tier of its own, after all the others.
+    pub fn add_tracker(&mut self, url: &str) {
+        if self.announce.is_empty() {
+            self.announce = url.to_string();
+        } else if self.announce_list.is_empty() {
+            self.announce_list = vec![vec![self.announce.clone()]];
+        }
 
-        let pieces = match info.get("pieces".as_bytes()) {
-            Some(BValue::Bytes(bytes)) => bytes,
-            _ => panic!("Torrent file does not contain pieces entry."),
+        if !self.announce_list.is_empty() || self.announce != url {
+            self.announce_list.push(vec![url.to_string()]);
+        }
+    }
+
+    pub fn remove_tracker(&mut self, url: &str) {
+        for tier in self.announce_list.iter_mut() {
+            tier.retain(|tracker| tracker != url);
+        }
+
+        self.announce_list.retain(|tier| !tier.is_empty());
+
+        if self.announce == url {
+            self.announce = self
+                .announce_list
+                .first()
+                .map(|tier| tier[0].clone())
+                .unwrap_or_default();
+        }
+    }
+
+    // Replaces `from` with `to` in every tracker URL, for example to swap out
+    // a passkey or move to a new tracker domain.
+    pub fn replace_tracker_urls(&mut self, from: &str, to: &str) {
+        self.announce = self.announce.replace(from, to);
+
+        for url in self.announce_list.iter_mut().flatten() {
+            *url = url.replace(from, to);
+        }
+    }
+
+    // Leaves the torrent trackerless.
+    pub fn strip_trackers(&mut self) {
+        self.announce.clear();
+        self.announce_list.clear();
+    }
+
+    // BEP 19 web seeds. `url-list` may be a single URL or a list of them.
+    pub fn web_seeds(&self) -> Vec<String> {
+        let url_list = self
+            .extra
+            .get(b"url-list".as_slice())
+            .and_then(|raw| bencode::decode_with_options(raw, DecodeOptions::untrusted()).ok());
+
+        let urls = match url_list {
+            Some(Value::Bytes(url)) => vec![Value::Bytes(url)],
+            Some(Value::List(urls)) => urls,
+            _ => Vec::new(),
         };
 
-        let piece_hashes = pieces.chunks(20).map(const_hex::encode).collect();
+        urls.into_iter()
+            .filter_map(|url| match url {
+                Value::Bytes(url) if !url.is_empty() => {
+                    Some(String::from_utf8_lossy(&url).to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Hybrid torrents are in both a v1 and a v2 swarm, so we announce both.
+    pub fn swarm_hashes(&self) -> Vec<InfoHash> {
+        let mut hashes = vec![self.hash];
+
+        if let Some(hash_v2) = &self.hash_v2 {
+            if hash_v2.truncated() != self.hash {
+                hashes.push(hash_v2.truncated());
+            }
+        }
+
+        hashes
+    }
+
+    // A complete torrent for metadata fetched through a magnet link, with
+    // the magnet's trackers and web seeds.
+    pub fn from_magnet(magnet_link: &MagnetLink, info: &[u8]) -> Result<Self, TorrentError> {
+        let mut torrent = Self::from_info_bytes(magnet_link.tracker_url().to_string(), info)?;
+
+        if magnet_link.trackers.len() > 1 {
+            torrent.announce_list = magnet_link.tracker_tiers();
+        }
+
+        if !magnet_link.web_seeds.is_empty() {
+            let web_seeds = magnet_link
+                .web_seeds
+                .iter()
+                .map(|url| url.as_str().into())
+                .collect();
+
+            torrent.extra.insert(
+                b"url-list".to_vec(),
+                bencode::encode(&Value::List(web_seeds)),
+            );
+        }
+
+        Ok(torrent)
+    }
+
+    pub fn from_info_bytes(announce: String, info: &[u8]) -> Result<Self, TorrentError> {
+        Ok(Metainfo {
+            announce: Some(announce),
+            info: Info::from_bytes(info)?,
+            ..Default::default()
+        }
+        .into())
+    }
+}
+
+impl From<Metainfo> for Torrent {
+    fn from(metainfo: Metainfo) -> Self {
+        // NOTE: trackerless torrents are left with an empty announce URL.
+        let announce = metainfo.tracker_url().unwrap_or_default().to_string();
+        let info = metainfo.info;
+
+        // Whatever `Torrent` doesn't have a field for goes back into `extra`,
+        // so it survives a round trip through `to_bytes`.
+        let mut extra = metainfo.extra;
+
+        if let Some(created_by) = metainfo.created_by {
+            extra.insert(
+                b"created by".to_vec(),
+                bencode::encode(&created_by.as_str().into()),
+            );
+        }
 
-        let encoded_info = serde_bencode::to_bytes(&BValue::Dict(info.clone()))?;
+        if let Some(creation_date) = metainfo.creation_date {
+            extra.insert(
+                b"creation date".to_vec(),
+                bencode::encode(&Value::Int(creation_date)),
+            );
+        }
 
-        let hash = calculate_hash(&encoded_info);
+        if let Some(encoding) = metainfo.encoding {
+            extra.insert(
+                b"encoding".to_vec(),
+                bencode::encode(&encoding.as_str().into()),
+            );
+        }
 
-        Ok(Self {
+        if !metainfo.piece_layers.is_empty() {
+            let layers = metainfo
+                .piece_layers
+                .iter()
+                .map(|(root, layer)| (root.to_vec(), Value::Bytes(layer.concat())))
+                .collect();
+
+            extra.insert(
+                b"piece layers".to_vec(),
+                bencode::encode(&Value::Dict(layers)),
+            );
+        }
+
+        // NOTE: v2-only torrents have no SHA-1 piece hashes, so we can only
+        // download them piece by piece once pieces are checked against the
+        // piece layers instead.
+        let (hash, files) = match info.is_v1() {
+            true => (info.hash, info.files),
+            false => (
+                info.truncated_hash_v2().unwrap_or_default(),
+                info.file_tree
+                    .into_iter()
+                    .map(|file| TorrentFile {
+                        length: file.length,
+                        path: file.path,
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+        };
+
+        Self {
             announce,
-            length,
+            announce_list: metainfo.announce_list,
+            length: info.length,
             hash,
-            piece_length,
-            piece_hashes,
-        })
+            hash_v2: info.hash_v2,
+            piece_length: info.piece_length,
+            piece_hashes: info.piece_hashes,
+            name: info.name.unwrap_or_default(),
+            files,
+            comment: metainfo.comment,
+            info_bytes: info.raw,
+            extra,
+        }
     }
 }
 
@@ -74,7 +352,7 @@ mod tests {
     #[test]
     fn test_new_torrent_from_bytes() {
         let input_info = "d6:lengthi420e12:piece lengthi512e6:pieces20:01234567890123456789e";
-        let expected_hash = calculate_hash(input_info.as_bytes());
+        let expected_hash = InfoHash::of(input_info.as_bytes());
         let input = format!("d8:announce8:fake_url4:info{}e", input_info);
 
         let expected_torrent = Torrent {
@@ -83,10 +361,181 @@ mod tests {
             hash: expected_hash,
             piece_length: 512,
             piece_hashes: vec!["3031323334353637383930313233343536373839".to_string()],
+            info_bytes: input_info.as_bytes().to_vec(),
+            ..Default::default()
         };
 
         let actual_torrent = Torrent::from_bytes(input.as_bytes()).unwrap();
 
         assert_eq!(expected_torrent, actual_torrent);
     }
+
+    #[test]
+    fn test_new_torrent_from_bytes_hashes_original_info() {
+        // Keys out of order and a leading zero, so re-encoding would change the bytes.
+        let input_info = "d12:piece lengthi512e6:lengthi0420e6:pieces20:01234567890123456789e";
+        let expected_hash = InfoHash::of(input_info.as_bytes());
+        let input = format!("d8:announce8:fake_url4:info{}e", input_info);
+
+        let actual_torrent = Torrent::from_bytes(input.as_bytes()).unwrap();
+
+        assert_eq!(expected_hash, actual_torrent.hash);
+        assert_eq!(420, actual_torrent.length);
+    }
+
+    #[test]
+    fn test_new_torrent_from_info_bytes() {
+        let input_info = "d6:lengthi420e12:piece lengthi512e6:pieces20:01234567890123456789e";
+
+        let expected_torrent =
+            Torrent::from_bytes(format!("d8:announce8:fake_url4:info{}e", input_info).as_bytes())
+                .unwrap();
+
+        let actual_torrent =
+            Torrent::from_info_bytes("fake_url".to_string(), input_info.as_bytes()).unwrap();
+
+        assert_eq!(expected_torrent, actual_torrent);
+    }
+
+    #[test]
+    fn test_new_multi_file_torrent_from_bytes() {
+        let input_info =
+            "d5:filesld6:lengthi100e4:pathl1:a5:b.txteed6:lengthi320e4:pathl5:c.txteee\
+            4:name6:sample12:piece lengthi512e6:pieces20:01234567890123456789e";
+        let input = format!("d8:announce8:fake_url4:info{}e", input_info);
+
+        let expected_files = vec![
+            TorrentFile {
+                length: 100,
+                path: vec!["a".to_string(), "b.txt".to_string()],
+                ..Default::default()
+            },
+            TorrentFile {
+                length: 320,
+                path: vec!["c.txt".to_string()],
+                ..Default::default()
+            },
+        ];
+
+        let actual_torrent = Torrent::from_bytes(input.as_bytes()).unwrap();
+
+        assert_eq!(420, actual_torrent.length);
+        assert_eq!("sample", actual_torrent.name);
+        assert_eq!(expected_files, actual_torrent.files);
+        assert_eq!(InfoHash::of(input_info.as_bytes()), actual_torrent.hash);
+    }
+
+    #[test]
+    fn test_new_torrent_with_announce_list() {
+        let input_info = "d6:lengthi420e12:piece lengthi512e6:pieces20:01234567890123456789e";
+        let input = format!(
+            "d8:announce5:first13:announce-listll5:first6:secondeli42eel5:thirdee4:info{}e",
+            input_info
+        );
+
+        let actual_torrent = Torrent::from_bytes(input.as_bytes()).unwrap();
+
+        assert_eq!("first", actual_torrent.announce);
+        assert_eq!(
+            vec![
+                vec!["first".to_string(), "second".to_string()],
+                vec!["third".to_string()],
+            ],
+            actual_torrent.announce_list
+        );
+        assert_eq!(actual_torrent.announce_list, actual_torrent.tracker_tiers());
+    }
+
+    #[test]
+    fn test_to_bytes_keeps_info_and_extra_entries() {
+        // Non-canonical on purpose, so it would change if it were re-encoded.
+        let input_info = "d12:piece lengthi512e6:lengthi0420e6:pieces20:01234567890123456789e";
+        let input = format!(
+            "d8:announce8:fake_url7:comment3:old10:created by4:test4:info{}8:url-listl4:seedee",
+            input_info
+        );
+
+        let mut torrent = Torrent::from_bytes(input.as_bytes()).unwrap();
+        assert_eq!(input.as_bytes(), torrent.to_bytes());
+
+        torrent.comment = Some("new".to_string());
+        torrent.replace_tracker_urls("fake", "real");
+        torrent.add_tracker("backup");
+
+        let edited = Torrent::from_bytes(&torrent.to_bytes()).unwrap();
+
+        assert_eq!(InfoHash::of(input_info.as_bytes()), edited.hash);
+        assert_eq!(input_info.as_bytes(), edited.info_bytes);
+        assert_eq!("real_url", edited.announce);
+        assert_eq!(
+            vec![vec!["real_url".to_string()], vec!["backup".to_string()]],
+            edited.announce_list
+        );
+        assert_eq!(Some("new".to_string()), edited.comment);
+        assert_eq!(torrent.extra, edited.extra);
+        assert_eq!(2, edited.extra.len());
+        assert_eq!(vec!["seed".to_string()], edited.web_seeds());
+    }
+
+    #[test]
+    fn test_remove_and_strip_trackers() {
+        let mut torrent = Torrent {
+            announce: "a".to_string(),
+            announce_list: vec![
+                vec!["a".to_string(), "b".to_string()],
+                vec!["c".to_string()],
+            ],
+            info_bytes: b"de".to_vec(),
+            ..Default::default()
+        };
+
+        torrent.remove_tracker("a");
+        assert_eq!("b", torrent.announce);
+
+        torrent.remove_tracker("b");
+        assert_eq!("c", torrent.announce);
+        assert_eq!(vec![vec!["c".to_string()]], torrent.announce_list);
+
+        torrent.strip_trackers();
+        assert!(torrent.tracker_tiers().is_empty());
+        assert_eq!(b"d4:infodee".to_vec(), torrent.to_bytes());
+    }
+
+    #[test]
+    fn test_from_magnet() {
+        let info = "d6:lengthi1e4:name1:a12:piece lengthi1e6:pieces20:01234567890123456789e";
+        let magnet_link: MagnetLink = format!(
+            "magnet:?xt=urn:btih:{}&tr=http%3A%2F%2Fa&tr=http%3A%2F%2Fb&ws=http%3A%2F%2Fseed",
+            InfoHash::of(info.as_bytes())
+        )
+        .parse()
+        .unwrap();
+
+        let torrent = Torrent::from_magnet(&magnet_link, info.as_bytes()).unwrap();
+
+        assert_eq!(
+            format!(
+                "d8:announce8:http://a13:announce-listll8:http://ael8:http://bee\
+                4:info{}8:url-listl11:http://seedee",
+                info
+            )
+            .into_bytes(),
+            torrent.to_bytes()
+        );
+        assert_eq!(magnet_link.hash, torrent.hash);
+    }
+
+    #[test]
+    fn test_hybrid_swarm_hashes() {
+        let torrent = Torrent {
+            hash: InfoHash([0xaa; 20]),
+            hash_v2: Some(InfoHashV2([0xbb; 32])),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vec![InfoHash([0xaa; 20]), InfoHash([0xbb; 20])],
+            torrent.swarm_hashes()
+        );
+    }
 }
@@ -0,0 +1,867 @@
+use super::{
+    merkle::{self, MerkleHash, MERKLE_BLOCK_SIZE},
+    TorrentError,
+};
+use crate::bencode::{self, Value};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+};
+
+const MIN_PIECE_LENGTH: u64 = 16 * 1024;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+// When picking a piece length ourselves, we aim for roughly this many pieces.
+const TARGET_PIECE_COUNT: u64 = 1500;
+
+// Builds a .torrent file from a file or directory on disk.
+//
+// NOTE: the output only depends on the inputs given here and the data on
+// disk. Files are always added in sorted order, and nothing time-based is
+// added unless asked for, so the same input always gives the same info hash.
+#[derive(Clone, Debug)]
+pub struct TorrentBuilder {
+    path: PathBuf,
+    name: Option<String>,
+    piece_length: Option<u64>,
+    announce_list: Vec<Vec<String>>,
+    web_seeds: Vec<String>,
+    comment: Option<String>,
+    created_by: Option<String>,
+    creation_date: Option<i64>,
+    private: bool,
+    source: Option<String>,
+    threads: Option<usize>,
+    version: TorrentVersion,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TorrentVersion {
+    #[default]
+    V1,
+    // BEP 52 only, with SHA-256 merkle trees instead of SHA-1 piece hashes.
+    V2,
+    // Both at once, so the torrent works in v1 and v2 swarms.
+    Hybrid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct InputFile {
+    // `None` for BEP 47 padding, which reads as all zeros.
+    path: Option<PathBuf>,
+    // Relative to the directory being added. Empty when adding a single file.
+    components: Vec<String>,
+    length: u64,
+}
+
+impl TorrentBuilder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            name: None,
+            piece_length: None,
+            announce_list: Vec::new(),
+            web_seeds: Vec::new(),
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            private: false,
+            source: None,
+            threads: None,
+            version: TorrentVersion::default(),
+        }
+    }
+
+    pub fn version(mut self, version: TorrentVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    // Defaults to the name of the file or directory.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    // Must be a power of two, and at least 16 KiB. Picked based on the total
+    // size if not given.
+    pub fn piece_length(mut self, piece_length: u64) -> Self {
+        self.piece_length = Some(piece_length);
+        self
+    }
+
+    // Adds a tracker in a tier of its own.
+    pub fn tracker(self, url: impl Into<String>) -> Self {
+        self.tracker_tier(vec![url.into()])
+    }
+
+    // Adds a BEP 12 tier of trackers, which clients will try in random order.
+    pub fn tracker_tier(mut self, tier: Vec<String>) -> Self {
+        if !tier.is_empty() {
+            self.announce_list.push(tier);
+        }
+        self
+    }
+
+    // BEP 19 web seed URL.
+    pub fn web_seed(mut self, url: impl Into<String>) -> Self {
+        self.web_seeds.push(url.into());
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn created_by(mut self, created_by: impl Into<String>) -> Self {
+        self.created_by = Some(created_by.into());
+        self
+    }
+
+    pub fn creation_date(mut self, creation_date: i64) -> Self {
+        self.creation_date = Some(creation_date);
+        self
+    }
+
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    // How many threads to hash pieces with. Defaults to one per CPU.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    // Hashes everything and returns the bencoded .torrent file.
+    pub fn build(&self) -> Result<Vec<u8>, TorrentError> {
+        let is_dir = fs::metadata(&self.path)?.is_dir();
+
+        let files = match is_dir {
+            true => collect_files(&self.path)?,
+            false => vec![InputFile {
+                path: Some(self.path.clone()),
+                components: Vec::new(),
+                length: fs::metadata(&self.path)?.len(),
+            }],
+        };
+
+        if files.is_empty() {
+            return Err(TorrentError::NoFiles(self.path.clone()));
+        }
+
+        let total_length = files.iter().map(|file| file.length).sum();
+
+        let piece_length = match self.piece_length {
+            Some(len) if len.is_power_of_two() && len >= MIN_PIECE_LENGTH => len,
+            Some(len) => return Err(TorrentError::InvalidPieceLength(len)),
+            None => auto_piece_length(total_length),
+        };
+
+        let threads = match self.threads {
+            Some(threads) => threads,
+            None => thread::available_parallelism().map_or(1, usize::from),
+        };
+
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .path
+                .canonicalize()?
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+
+        let mut info = BTreeMap::<Vec<u8>, Value>::new();
+        let mut piece_layers = BTreeMap::<Vec<u8>, Value>::new();
+
+        if self.version != TorrentVersion::V1 {
+            let leaves = hash_files_v2(&files, threads)?;
+            let mut file_tree = BTreeMap::<Vec<u8>, Value>::new();
+
+            for (file, leaves) in files.iter().zip(leaves) {
+                let mut entry =
+                    BTreeMap::from([(b"length".to_vec(), Value::Int(file.length as i64))]);
+
+                if let Some(root) = merkle::pieces_root_from_blocks(&leaves) {
+                    entry.insert(b"pieces root".to_vec(), Value::Bytes(root.to_vec()));
+
+                    if file.length > piece_length {
+                        let layer = merkle::piece_layer_from_blocks(&leaves, piece_length as usize);
+                        piece_layers.insert(root.to_vec(), Value::Bytes(layer.concat()));
+                    }
+                }
+
+                // A single file goes in the tree under the torrent's name.
+                let path = match is_dir {
+                    true => file.components.clone(),
+                    false => vec![name.clone()],
+                };
+
+                insert_file_tree_entry(&mut file_tree, &path, entry);
+            }
+
+            info.insert(b"file tree".to_vec(), Value::Dict(file_tree));
+            info.insert(b"meta version".to_vec(), Value::Int(2));
+        }
+
+        if self.version != TorrentVersion::V2 {
+            // Hybrid torrents need every file to start on a piece boundary,
+            // like v2 does, so we pad between them.
+            let files = match (self.version, is_dir) {
+                (TorrentVersion::Hybrid, true) => pad_files(files, piece_length),
+                _ => files,
+            };
+
+            let pieces = hash_pieces(&files, total_length_of(&files), piece_length, threads)?;
+
+            match is_dir {
+                true => {
+                    let files = files.iter().map(file_entry).collect();
+
+                    info.insert(b"files".to_vec(), Value::List(files));
+                }
+                false => {
+                    info.insert(b"length".to_vec(), Value::Int(total_length as i64));
+                }
+            }
+
+            info.insert(b"pieces".to_vec(), Value::Bytes(pieces));
+        }
+
+        info.insert(b"name".to_vec(), name.as_str().into());
+        info.insert(b"piece length".to_vec(), Value::Int(piece_length as i64));
+
+        if self.private {
+            info.insert(b"private".to_vec(), Value::Int(1));
+        }
+
+        if let Some(source) = &self.source {
+            info.insert(b"source".to_vec(), source.as_str().into());
+        }
+
+        let mut torrent = BTreeMap::<Vec<u8>, Value>::new();
+
+        if let Some(tracker) = self.announce_list.first() {
+            torrent.insert(b"announce".to_vec(), tracker[0].as_str().into());
+        }
+
+        // A single tracker doesn't need the announce-list at all.
+        if self.announce_list.iter().map(Vec::len).sum::<usize>() > 1 {
+            let tiers = self
+                .announce_list
+                .iter()
+                .map(|tier| Value::List(tier.iter().map(|url| url.as_str().into()).collect()))
+                .collect();
+
+            torrent.insert(b"announce-list".to_vec(), Value::List(tiers));
+        }
+
+        if let Some(comment) = &self.comment {
+            torrent.insert(b"comment".to_vec(), comment.as_str().into());
+        }
+
+        if let Some(created_by) = &self.created_by {
+            torrent.insert(b"created by".to_vec(), created_by.as_str().into());
+        }
+
+        if let Some(creation_date) = self.creation_date {
+            torrent.insert(b"creation date".to_vec(), Value::Int(creation_date));
+        }
+
+        torrent.insert(b"info".to_vec(), Value::Dict(info));
+
+        if !piece_layers.is_empty() {
+            torrent.insert(b"piece layers".to_vec(), Value::Dict(piece_layers));
+        }
+
+        if !self.web_seeds.is_empty() {
+            let web_seeds = self
+                .web_seeds
+                .iter()
+                .map(|url| url.as_str().into())
+                .collect();
+
+            torrent.insert(b"url-list".to_vec(), Value::List(web_seeds));
+        }
+
+        Ok(bencode::encode(&Value::Dict(torrent)))
+    }
+}
+
+// Picks a power of two that gives roughly `TARGET_PIECE_COUNT` pieces.
+pub(crate) fn auto_piece_length(total_length: u64) -> u64 {
+    (total_length / TARGET_PIECE_COUNT)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+fn total_length_of(files: &[InputFile]) -> u64 {
+    files.iter().map(|file| file.length).sum()
+}
+
+fn file_entry(file: &InputFile) -> Value {
+    let path = file
+        .components
+        .iter()
+        .map(|component| component.as_str().into())
+        .collect();
+
+    let mut entry = BTreeMap::from([
+        (b"length".to_vec(), Value::Int(file.length as i64)),
+        (b"path".to_vec(), Value::List(path)),
+    ]);
+
+    if file.path.is_none() {
+        entry.insert(b"attr".to_vec(), "p".into());
+    }
+
+    Value::Dict(entry)
+}
+
+// Adds a BEP 47 padding file after every file that doesn't end on a piece
+// boundary, except the last one.
+fn pad_files(files: Vec<InputFile>, piece_length: u64) -> Vec<InputFile> {
+    let count = files.len();
+    let mut padded = Vec::with_capacity(count * 2);
+
+    for (i, file) in files.into_iter().enumerate() {
+        let remainder = file.length % piece_length;
+        padded.push(file);
+
+        if remainder != 0 && i + 1 < count {
+            let length = piece_length - remainder;
+
+            padded.push(InputFile {
+                path: None,
+                components: vec![".pad".to_string(), length.to_string()],
+                length,
+            });
+        }
+    }
+
+    padded
+}
+
+fn insert_file_tree_entry(
+    tree: &mut BTreeMap<Vec<u8>, Value>,
+    path: &[String],
+    entry: BTreeMap<Vec<u8>, Value>,
+) {
+    let node = path.iter().fold(tree, |node, component| {
+        match node
+            .entry(component.as_bytes().to_vec())
+            .or_insert_with(|| Value::Dict(BTreeMap::new()))
+        {
+            Value::Dict(child) => child,
+            _ => unreachable!(),
+        }
+    });
+
+    node.insert(Vec::new(), Value::Dict(entry));
+}
+
+// The SHA-256 hash of every 16 KiB block of each file, for building the v2
+// merkle trees. Like `hash_pieces`, the blocks of all the files together are
+// split into one contiguous run per thread, so one big file still gets
+// hashed by every thread.
+fn hash_files_v2(
+    files: &[InputFile],
+    threads: usize,
+) -> Result<Vec<Vec<MerkleHash>>, TorrentError> {
+    let block_size = MERKLE_BLOCK_SIZE as u64;
+
+    // Where each file starts within the stream of every file, and which
+    // blocks of that stream are its own.
+    let mut file_starts = Vec::with_capacity(files.len());
+    let mut first_blocks = Vec::with_capacity(files.len());
+    let mut block_ends = Vec::with_capacity(files.len());
+    let (mut offset, mut block_count) = (0, 0);
+
+    for file in files {
+        file_starts.push(offset);
+        first_blocks.push(block_count);
+
+        offset += file.length;
+        block_count += file.length.div_ceil(block_size) as usize;
+        block_ends.push(block_count);
+    }
+
+    let blocks_per_thread = block_count.div_ceil(threads).max(1);
+
+    let runs = thread::scope(|scope| {
+        let handles = (0..block_count)
+            .step_by(blocks_per_thread)
+            .map(|first| {
+                let last = usize::min(first + blocks_per_thread, block_count);
+                let (file_starts, first_blocks, block_ends) =
+                    (&file_starts, &first_blocks, &block_ends);
+
+                scope.spawn(move || {
+                    let mut reader = PieceReader::new(files);
+                    let mut leaves = Vec::with_capacity(last - first);
+                    let mut buffer = vec![0; MERKLE_BLOCK_SIZE];
+                    let mut index = block_ends.partition_point(|end| *end <= first);
+
+                    for block in first..last {
+                        // Empty files don't have any blocks, so they're skipped.
+                        while block_ends[index] <= block {
+                            index += 1;
+                        }
+
+                        let start = (block - first_blocks[index]) as u64 * block_size;
+                        let length = u64::min(block_size, files[index].length - start);
+                        let data = &mut buffer[..length as usize];
+
+                        reader.read_at(file_starts[index] + start, data)?;
+                        leaves.extend(merkle::block_hashes(data));
+                    }
+
+                    Ok::<_, TorrentError>(leaves)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Hashing thread panicked."))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    let mut leaves = runs.concat().into_iter();
+
+    Ok(files
+        .iter()
+        .map(|file| {
+            let blocks = file.length.div_ceil(block_size) as usize;
+            leaves.by_ref().take(blocks).collect()
+        })
+        .collect())
+}
+
+// Every file under `root`, sorted by path so the order doesn't depend on the
+// filesystem.
+fn collect_files(root: &Path) -> Result<Vec<InputFile>, TorrentError> {
+    let mut files = Vec::new();
+    let mut directories = vec![(root.to_path_buf(), Vec::<String>::new())];
+
+    while let Some((directory, components)) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let metadata = fs::metadata(entry.path())?;
+
+            let mut components = components.clone();
+            components.push(entry.file_name().to_string_lossy().to_string());
+
+            if metadata.is_dir() {
+                directories.push((entry.path(), components));
+            } else {
+                files.push(InputFile {
+                    path: Some(entry.path()),
+                    components,
+                    length: metadata.len(),
+                });
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.components.cmp(&b.components));
+
+    Ok(files)
+}
+
+// Hashes every piece, splitting them into one contiguous run per thread.
+fn hash_pieces(
+    files: &[InputFile],
+    total_length: u64,
+    piece_length: u64,
+    threads: usize,
+) -> Result<Vec<u8>, TorrentError> {
+    let piece_count = total_length.div_ceil(piece_length) as usize;
+    let pieces_per_thread = piece_count.div_ceil(threads).max(1);
+
+    let runs = thread::scope(|scope| {
+        let handles = (0..piece_count)
+            .step_by(pieces_per_thread)
+            .map(|first| {
+                let last = usize::min(first + pieces_per_thread, piece_count);
+
+                scope.spawn(move || {
+                    let mut reader = PieceReader::new(files);
+                    let mut hashes = Vec::with_capacity((last - first) * 20);
+                    let mut buffer = Vec::new();
+
+                    for index in first..last {
+                        let start = index as u64 * piece_length;
+                        let length = u64::min(piece_length, total_length - start);
+
+                        buffer.resize(length as usize, 0);
+                        reader.read_at(start, &mut buffer)?;
+                        hashes.extend_from_slice(&Sha1::digest(&buffer));
+                    }
+
+                    Ok::<_, TorrentError>(hashes)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Hashing thread panicked."))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    Ok(runs.concat())
+}
+
+// Reads byte ranges out of the files as if they were one long stream,
+// keeping the last file open since reads are mostly sequential.
+struct PieceReader<'a> {
+    files: &'a [InputFile],
+    // Where each file ends within the stream, for finding the first file a
+    // read touches without going through all of them.
+    ends: Vec<u64>,
+    open_file: Option<(usize, File)>,
+}
+
+impl<'a> PieceReader<'a> {
+    fn new(files: &'a [InputFile]) -> Self {
+        let ends = files
+            .iter()
+            .scan(0, |end, file| {
+                *end += file.length;
+                Some(*end)
+            })
+            .collect();
+
+        Self {
+            files,
+            ends,
+            open_file: None,
+        }
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), TorrentError> {
+        let mut index = self.ends.partition_point(|end| *end <= offset);
+        let mut written = 0;
+
+        while written < buffer.len() && index < self.files.len() {
+            let file = &self.files[index];
+            let file_end = self.ends[index];
+            let file_start = file_end - file.length;
+            let position = offset + written as u64;
+
+            if file.length > 0 {
+                let length = usize::min(buffer.len() - written, (file_end - position) as usize);
+
+                let target = &mut buffer[written..][..length];
+
+                match &file.path {
+                    Some(path) => {
+                        if !matches!(&self.open_file, Some((i, _)) if *i == index) {
+                            self.open_file = Some((index, File::open(path)?));
+                        }
+
+                        let (_, handle) = self.open_file.as_mut().unwrap();
+                        handle.seek(SeekFrom::Start(position - file_start))?;
+                        handle.read_exact(target)?;
+                    }
+                    None => target.fill(0),
+                }
+
+                written += length;
+            }
+
+            index += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate_hash, Metainfo, TorrentFile};
+
+    fn sample_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        fs::create_dir_all(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("b.txt"), vec![b'b'; 20_000]).unwrap();
+        fs::write(dir.path().join("a.txt"), vec![b'a'; 10_000]).unwrap();
+        fs::write(dir.path().join("empty.txt"), b"").unwrap();
+        fs::write(dir.path().join("nested").join("c.txt"), vec![b'c'; 5_000]).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_build_directory() {
+        let dir = sample_dir();
+
+        let bytes = TorrentBuilder::new(dir.path())
+            .name("sample")
+            .tracker_tier(vec!["http://a".to_string(), "http://b".to_string()])
+            .tracker("http://c")
+            .web_seed("http://seed")
+            .comment("hello")
+            .private(true)
+            .source("test")
+            .build()
+            .unwrap();
+
+        let metainfo = Metainfo::from_bytes(&bytes).unwrap();
+
+        let mut data = vec![b'a'; 10_000];
+        data.extend(vec![b'b'; 20_000]);
+        data.extend(vec![b'c'; 5_000]);
+
+        assert_eq!(Some("http://a".to_string()), metainfo.announce);
+        assert_eq!(
+            vec![
+                vec!["http://a".to_string(), "http://b".to_string()],
+                vec!["http://c".to_string()],
+            ],
+            metainfo.announce_list
+        );
+        assert_eq!(Some("hello".to_string()), metainfo.comment);
+        assert_eq!(Some("sample".to_string()), metainfo.info.name);
+        assert!(metainfo.info.private);
+        assert_eq!(Some("test".to_string()), metainfo.info.source);
+        assert_eq!(35_000, metainfo.info.length);
+        assert_eq!(
+            vec![
+                TorrentFile {
+                    length: 10_000,
+                    path: vec!["a.txt".to_string()],
+                    ..Default::default()
+                },
+                TorrentFile {
+                    length: 20_000,
+                    path: vec!["b.txt".to_string()],
+                    ..Default::default()
+                },
+                TorrentFile {
+                    length: 0,
+                    path: vec!["empty.txt".to_string()],
+                    ..Default::default()
+                },
+                TorrentFile {
+                    length: 5_000,
+                    path: vec!["nested".to_string(), "c.txt".to_string()],
+                    ..Default::default()
+                },
+            ],
+            metainfo.info.files
+        );
+        assert_eq!(
+            data.chunks(16 * 1024)
+                .map(calculate_hash)
+                .collect::<Vec<_>>(),
+            metainfo.info.piece_hashes
+        );
+    }
+
+    #[test]
+    fn test_build_is_deterministic() {
+        let dir = sample_dir();
+        let builder = TorrentBuilder::new(dir.path()).tracker("http://a");
+
+        let single_threaded = builder.clone().threads(1).build().unwrap();
+        let multi_threaded = builder.clone().threads(3).build().unwrap();
+
+        assert_eq!(single_threaded, multi_threaded);
+        assert_eq!(single_threaded, builder.build().unwrap());
+    }
+
+    #[test]
+    fn test_build_single_file() {
+        let dir = sample_dir();
+        let path = dir.path().join("a.txt");
+
+        let bytes = TorrentBuilder::new(&path)
+            .piece_length(32 * 1024)
+            .build()
+            .unwrap();
+        let metainfo = Metainfo::from_bytes(&bytes).unwrap();
+
+        assert_eq!(None, metainfo.announce);
+        assert_eq!(Some("a.txt".to_string()), metainfo.info.name);
+        assert_eq!(10_000, metainfo.info.length);
+        assert!(metainfo.info.files.is_empty());
+        assert_eq!(32 * 1024, metainfo.info.piece_length);
+        assert_eq!(
+            vec![calculate_hash(&[b'a'; 10_000])],
+            metainfo.info.piece_hashes
+        );
+    }
+
+    #[test]
+    fn test_build_hybrid() {
+        let dir = sample_dir();
+
+        let bytes = TorrentBuilder::new(dir.path())
+            .piece_length(16 * 1024)
+            .version(TorrentVersion::Hybrid)
+            .build()
+            .unwrap();
+
+        // Parsing also checks the piece layers against each pieces root.
+        let metainfo = Metainfo::from_bytes(&bytes).unwrap();
+        let info = &metainfo.info;
+
+        assert!(info.is_hybrid());
+        assert_eq!(
+            vec![
+                vec!["a.txt".to_string()],
+                vec!["b.txt".to_string()],
+                vec!["empty.txt".to_string()],
+                vec!["nested".to_string(), "c.txt".to_string()],
+            ],
+            info.file_tree
+                .iter()
+                .map(|file| file.path.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            merkle::pieces_root(&[b'b'; 20_000]),
+            info.file_tree[1].pieces_root
+        );
+        assert_eq!(None, info.file_tree[2].pieces_root);
+        assert_eq!(1, metainfo.piece_layers.len());
+
+        // Every file starts on a piece boundary in the v1 data too.
+        let mut data = vec![b'a'; 10_000];
+        data.resize(16 * 1024, 0);
+        data.extend(vec![b'b'; 20_000]);
+        data.resize(48 * 1024, 0);
+        data.extend(vec![b'c'; 5_000]);
+
+        assert_eq!(
+            data.chunks(16 * 1024)
+                .map(calculate_hash)
+                .collect::<Vec<_>>(),
+            info.piece_hashes
+        );
+        assert_eq!(
+            vec![".pad".to_string(), "6384".to_string()],
+            info.files[1].path
+        );
+        assert!(info.files[1].is_padding());
+    }
+
+    #[test]
+    fn test_build_v2() {
+        let dir = sample_dir();
+        let path = dir.path().join("b.txt");
+
+        let bytes = TorrentBuilder::new(&path)
+            .piece_length(16 * 1024)
+            .version(TorrentVersion::V2)
+            .build()
+            .unwrap();
+        let metainfo = Metainfo::from_bytes(&bytes).unwrap();
+
+        assert!(!metainfo.info.is_v1());
+        assert!(metainfo.info.piece_hashes.is_empty());
+        assert_eq!(20_000, metainfo.info.length);
+        assert_eq!(vec!["b.txt".to_string()], metainfo.info.file_tree[0].path);
+
+        let torrent = crate::Torrent::from(metainfo.clone());
+
+        assert_eq!(metainfo.info.truncated_hash_v2().unwrap(), torrent.hash);
+        assert_eq!(vec![torrent.hash], torrent.swarm_hashes());
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(matches!(
+            TorrentBuilder::new(dir.path()).build(),
+            Err(TorrentError::NoFiles(_))
+        ));
+
+        fs::write(dir.path().join("a.txt"), b"a").unwrap();
+
+        assert!(matches!(
+            TorrentBuilder::new(dir.path()).piece_length(20_000).build(),
+            Err(TorrentError::InvalidPieceLength(20_000))
+        ));
+    }
+
+    #[test]
+    fn test_auto_piece_length() {
+        assert_eq!(16 * 1024, auto_piece_length(0));
+        assert_eq!(16 * 1024, auto_piece_length(1024 * 1024));
+        assert_eq!(1024 * 1024, auto_piece_length(1024 * 1024 * 1024));
+        assert_eq!(16 * 1024 * 1024, auto_piece_length(u64::MAX / 2));
+    }
+
+    #[test]
+    fn test_piece_reader() {
+        let dir = sample_dir();
+
+        let file = |components: &[&str], length| InputFile {
+            path: Some(
+                components
+                    .iter()
+                    .fold(dir.path().to_path_buf(), |path, c| path.join(c)),
+            ),
+            components: components.iter().map(ToString::to_string).collect(),
+            length,
+        };
+
+        let files = vec![
+            file(&["a.txt"], 10_000),
+            file(&["empty.txt"], 0),
+            InputFile {
+                path: None,
+                components: vec![".pad".to_string(), "6384".to_string()],
+                length: 6_384,
+            },
+            file(&["nested", "c.txt"], 5_000),
+        ];
+
+        let mut reader = PieceReader::new(&files);
+        let mut buffer = vec![1; 4];
+
+        reader.read_at(9_998, &mut buffer).unwrap();
+        assert_eq!(b"aa\0\0", &buffer[..]);
+
+        reader.read_at(16 * 1024 - 1, &mut buffer).unwrap();
+        assert_eq!(b"\0ccc", &buffer[..]);
+
+        // Going back to an earlier file still works.
+        reader.read_at(0, &mut buffer).unwrap();
+        assert_eq!(b"aaaa", &buffer[..]);
+    }
+
+    #[test]
+    fn test_hash_files_v2_splits_blocks() {
+        let dir = sample_dir();
+        let files = collect_files(dir.path()).unwrap();
+
+        let expected = files
+            .iter()
+            .map(|file| merkle::block_hashes(&fs::read(file.path.as_ref().unwrap()).unwrap()))
+            .collect::<Vec<_>>();
+
+        // b.txt alone is two blocks, so some threads get part of a file.
+        for threads in [1, 2, 3, 16] {
+            assert_eq!(expected, hash_files_v2(&files, threads).unwrap());
+        }
+    }
+}
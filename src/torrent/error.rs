@@ -0,0 +1,22 @@
+use crate::bencode::BencodeError;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TorrentError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("invalid bencode: {0}")]
+    Bencode(#[from] BencodeError),
+    #[error("torrent is not a bencoded dictionary")]
+    NotADictionary,
+    // Fields are named by their path within the torrent, e.g. "info.files.0.length".
+    #[error("missing {0} entry")]
+    MissingField(String),
+    #[error("invalid {0} entry")]
+    InvalidField(String),
+    #[error("no files found in {0}")]
+    NoFiles(PathBuf),
+    #[error("piece length must be a power of two of at least 16 KiB, got {0}")]
+    InvalidPieceLength(u64),
+}
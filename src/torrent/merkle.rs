@@ -0,0 +1,175 @@
+use sha2::{Digest, Sha256};
+
+// BEP 52 merkle trees are always built from hashes of 16 KiB blocks.
+pub(crate) const MERKLE_BLOCK_SIZE: usize = 16 * 1024;
+
+pub type MerkleHash = [u8; 32];
+
+pub(crate) fn block_hashes(data: &[u8]) -> Vec<MerkleHash> {
+    data.chunks(MERKLE_BLOCK_SIZE)
+        .map(|block| Sha256::digest(block).into())
+        .collect()
+}
+
+// Builds the tree bottom up, padding `nodes` with `padding` until there are
+// `width` of them. `width` must be a power of two, and at least `nodes.len()`.
+pub(crate) fn merkle_root(nodes: &[MerkleHash], width: usize, padding: MerkleHash) -> MerkleHash {
+    let mut layer = nodes.to_vec();
+    layer.resize(width.max(1), padding);
+
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| {
+                let mut hasher = Sha256::new();
+                hasher.update(pair[0]);
+                hasher.update(pair[1]);
+                hasher.finalize().into()
+            })
+            .collect();
+    }
+
+    layer[0]
+}
+
+// The root of a tree with `width` leaves, all of them zero.
+fn zero_root(width: usize) -> MerkleHash {
+    merkle_root(&[], width, [0; 32])
+}
+
+// The `pieces root` of a file. Empty files don't have one.
+pub fn pieces_root(data: &[u8]) -> Option<MerkleHash> {
+    pieces_root_from_blocks(&block_hashes(data))
+}
+
+pub(crate) fn pieces_root_from_blocks(leaves: &[MerkleHash]) -> Option<MerkleHash> {
+    (!leaves.is_empty()).then(|| merkle_root(leaves, leaves.len().next_power_of_two(), [0; 32]))
+}
+
+// One hash per piece, each the root of that piece's blocks. The last piece
+// is padded out with zero leaves to a full piece.
+//
+// NOTE: BEP 52 only stores these for files larger than a single piece.
+pub fn piece_layer(data: &[u8], piece_length: usize) -> Vec<MerkleHash> {
+    piece_layer_from_blocks(&block_hashes(data), piece_length)
+}
+
+pub(crate) fn piece_layer_from_blocks(
+    leaves: &[MerkleHash],
+    piece_length: usize,
+) -> Vec<MerkleHash> {
+    let blocks_per_piece = piece_length / MERKLE_BLOCK_SIZE;
+
+    // BEP 52 pieces are at least one block, so there's no layer otherwise.
+    if blocks_per_piece == 0 {
+        return Vec::new();
+    }
+
+    leaves
+        .chunks(blocks_per_piece)
+        .map(|piece| merkle_root(piece, blocks_per_piece, [0; 32]))
+        .collect()
+}
+
+// NOTE: downloading v2 pieces is out of scope. Downloads only check SHA-1
+// piece hashes, which hybrid torrents have, and `FileInfo::new` refuses
+// v2-only torrents. This is for callers that fetch v2 pieces on their own.
+pub fn verify_piece_v2(data: &[u8], piece_length: usize, expected: &MerkleHash) -> bool {
+    let blocks_per_piece = piece_length / MERKLE_BLOCK_SIZE;
+
+    blocks_per_piece != 0
+        && data.len() <= piece_length
+        && merkle_root(&block_hashes(data), blocks_per_piece, [0; 32]) == *expected
+}
+
+// Checks a file's piece layer against its `pieces root`.
+pub fn verify_piece_layer(
+    layer: &[MerkleHash],
+    length: u64,
+    piece_length: usize,
+    pieces_root: &MerkleHash,
+) -> bool {
+    let blocks_per_piece = piece_length / MERKLE_BLOCK_SIZE;
+
+    if blocks_per_piece == 0 {
+        return false;
+    }
+
+    let blocks = length.div_ceil(MERKLE_BLOCK_SIZE as u64) as usize;
+    let width = blocks.next_power_of_two() / blocks_per_piece;
+
+    layer.len() == length.div_ceil(piece_length as u64) as usize
+        && merkle_root(layer, width, zero_root(blocks_per_piece)) == *pieces_root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_pair(left: MerkleHash, right: MerkleHash) -> MerkleHash {
+        Sha256::digest([left, right].concat()).into()
+    }
+
+    #[test]
+    fn test_pieces_root() {
+        let data = vec![7_u8; MERKLE_BLOCK_SIZE * 2 + 10];
+        let leaves = block_hashes(&data);
+
+        let expected = hash_pair(
+            hash_pair(leaves[0], leaves[1]),
+            hash_pair(leaves[2], [0; 32]),
+        );
+
+        assert_eq!(3, leaves.len());
+        assert_eq!(Some(expected), pieces_root(&data));
+        assert_eq!(None, pieces_root(&[]));
+
+        let single_block: MerkleHash = Sha256::digest(b"tiny").into();
+        assert_eq!(Some(single_block), pieces_root(b"tiny"));
+    }
+
+    #[test]
+    fn test_piece_length_below_block_size() {
+        let data = vec![1_u8; 40_000];
+        let root = pieces_root(&data).unwrap();
+
+        assert!(piece_layer(&data, 1).is_empty());
+        assert!(!verify_piece_layer(&[], data.len() as u64, 1, &root));
+        assert!(!verify_piece_v2(&data[..1], 1, &root));
+    }
+
+    #[test]
+    fn test_piece_layer() {
+        let piece_length = MERKLE_BLOCK_SIZE * 2;
+        let data = (0..piece_length * 2 + 1)
+            .map(|i| (i / 1000) as u8)
+            .collect::<Vec<_>>();
+
+        let layer = piece_layer(&data, piece_length);
+        let root = pieces_root(&data).unwrap();
+
+        assert_eq!(3, layer.len());
+        assert!(verify_piece_layer(
+            &layer,
+            data.len() as u64,
+            piece_length,
+            &root
+        ));
+        assert!(!verify_piece_layer(
+            &layer[..2],
+            data.len() as u64,
+            piece_length,
+            &root
+        ));
+
+        for (piece, hash) in data.chunks(piece_length).zip(&layer) {
+            assert!(verify_piece_v2(piece, piece_length, hash));
+        }
+
+        assert!(!verify_piece_v2(
+            &data[1..][..piece_length],
+            piece_length,
+            &layer[0]
+        ));
+    }
+}
@@ -0,0 +1,655 @@
+use super::{merkle, MerkleHash, TorrentError, TorrentFile};
+use crate::{
+    bencode::{self, DecodeOptions, ValueRef},
+    InfoHash, InfoHashV2,
+};
+use std::{collections::BTreeMap, fs};
+
+// Everything a .torrent file can tell us, as described in BEP 3, plus the
+// commonly used optional keys. Anything optional that's missing is `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metainfo {
+    pub announce: Option<String>,
+    // BEP 12 tiers of backup trackers. Empty if the torrent only has `announce`.
+    pub announce_list: Vec<Vec<String>>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    // Seconds since the Unix epoch.
+    pub creation_date: Option<i64>,
+    pub encoding: Option<String>,
+    pub info: Info,
+    // BEP 52: the piece hashes of every v2 file larger than one piece,
+    // keyed by the file's `pieces root`.
+    pub piece_layers: BTreeMap<MerkleHash, Vec<MerkleHash>>,
+    // Every other top-level entry, exactly as it was encoded, so that
+    // rewriting a torrent doesn't drop anything we don't understand.
+    pub extra: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Info {
+    pub name: Option<String>,
+    // For multi-file torrents, this is the sum of every file's length.
+    pub length: i64,
+    // Empty for single-file torrents, which only have a `length`.
+    pub files: Vec<TorrentFile>,
+    pub piece_length: i64,
+    pub piece_hashes: Vec<String>,
+    // BEP 27: peers should only come from the trackers in the torrent.
+    pub private: bool,
+    pub source: Option<String>,
+    // The SHA-1 info hash. Only meaningful for v1 and hybrid torrents.
+    pub hash: InfoHash,
+    // BEP 52 fields, for v2 and hybrid torrents.
+    pub meta_version: Option<i64>,
+    pub file_tree: Vec<FileTreeEntry>,
+    // The full SHA-256 info hash. Trackers and peers use the first 20 bytes.
+    pub hash_v2: Option<InfoHashV2>,
+    // The info dictionary exactly as it appeared in the input. Anything that
+    // writes the torrent back out must use this, or the info hash changes.
+    pub raw: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileTreeEntry {
+    pub path: Vec<String>,
+    pub length: i64,
+    // Empty files don't have one.
+    pub pieces_root: Option<MerkleHash>,
+}
+
+impl Metainfo {
+    pub fn from_file(path: &str) -> Result<Self, TorrentError> {
+        let contents = fs::read(path)?;
+
+        Self::from_bytes(&contents)
+    }
+
+    pub fn from_bytes(contents: &[u8]) -> Result<Self, TorrentError> {
+        Self::from_bytes_with_options(contents, DecodeOptions::default())
+    }
+
+    // Use `DecodeOptions::untrusted()` for torrents from somewhere we don't control.
+    pub fn from_bytes_with_options(
+        contents: &[u8],
+        options: DecodeOptions,
+    ) -> Result<Self, TorrentError> {
+        let data = bencode::parse_with_options(contents, options)?;
+
+        if data.as_dict().is_none() {
+            return Err(TorrentError::NotADictionary);
+        }
+
+        let announce_list = match data.get("announce-list") {
+            Some(tiers) => match tiers.as_list() {
+                Some(tiers) => Self::parse_announce_list(tiers),
+                None => return Err(TorrentError::InvalidField("announce-list".to_string())),
+            },
+            None => Vec::new(),
+        };
+
+        let info = match data.get("info") {
+            Some(info) => Info::from_value(info)?,
+            None => return Err(TorrentError::MissingField("info".to_string())),
+        };
+
+        let piece_layers = match data.get("piece layers") {
+            Some(layers) => Self::parse_piece_layers(layers)?,
+            None => BTreeMap::new(),
+        };
+
+        let metainfo = Self {
+            announce: get_string(&data, "announce", "")?,
+            announce_list,
+            comment: get_string(&data, "comment", "")?,
+            created_by: get_string(&data, "created by", "")?,
+            creation_date: get_int(&data, "creation date", "")?,
+            encoding: get_string(&data, "encoding", "")?,
+            info,
+            piece_layers,
+            extra: Self::parse_extra(&data),
+        };
+
+        metainfo.verify_piece_layers()?;
+
+        Ok(metainfo)
+    }
+
+    // Every v2 file larger than a piece needs a piece layer that hashes up
+    // to its `pieces root`, otherwise we'd have nothing to check pieces against.
+    pub fn verify_piece_layers(&self) -> Result<(), TorrentError> {
+        let piece_length = self.info.piece_length as usize;
+
+        for file in &self.info.file_tree {
+            let pieces_root = match &file.pieces_root {
+                Some(root) if file.length > self.info.piece_length => root,
+                _ => continue,
+            };
+
+            let is_valid = self.piece_layers.get(pieces_root).is_some_and(|layer| {
+                merkle::verify_piece_layer(layer, file.length as u64, piece_length, pieces_root)
+            });
+
+            if !is_valid {
+                return Err(TorrentError::InvalidField(format!(
+                    "piece layers.{}",
+                    file.path.join("/")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_piece_layers(
+        layers: &ValueRef,
+    ) -> Result<BTreeMap<MerkleHash, Vec<MerkleHash>>, TorrentError> {
+        let invalid = || TorrentError::InvalidField("piece layers".to_string());
+
+        layers
+            .as_dict()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|(root, layer)| {
+                let root = MerkleHash::try_from(*root).map_err(|_| invalid())?;
+                let layer = layer
+                    .as_bytes()
+                    .filter(|layer| layer.len() % 32 == 0)
+                    .ok_or_else(invalid)?
+                    .chunks(32)
+                    .map(|hash| MerkleHash::try_from(hash).unwrap())
+                    .collect();
+
+                Ok((root, layer))
+            })
+            .collect()
+    }
+
+    // The main tracker, which is the first one in the announce-list when
+    // there's no `announce` entry.
+    pub fn tracker_url(&self) -> Option<&str> {
+        match &self.announce {
+            Some(url) => Some(url),
+            None => self.announce_list.first().map(|tier| tier[0].as_str()),
+        }
+    }
+
+    fn parse_extra(data: &ValueRef) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        const KNOWN_KEYS: [&[u8]; 8] = [
+            b"announce",
+            b"announce-list",
+            b"comment",
+            b"created by",
+            b"creation date",
+            b"encoding",
+            b"info",
+            b"piece layers",
+        ];
+
+        data.as_dict()
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| !KNOWN_KEYS.contains(key))
+            .map(|(key, value)| (key.to_vec(), value.raw().to_vec()))
+            .collect()
+    }
+
+    // NOTE: anything in the list that isn't a string gets skipped, along with
+    // any tier that ends up empty, rather than failing the whole torrent.
+    fn parse_announce_list(tiers: &[ValueRef]) -> Vec<Vec<String>> {
+        tiers
+            .iter()
+            .filter_map(ValueRef::as_list)
+            .map(|tier| {
+                tier.iter()
+                    .filter_map(ValueRef::as_bytes)
+                    .map(|url| String::from_utf8_lossy(url).to_string())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|tier| !tier.is_empty())
+            .collect()
+    }
+}
+
+impl Info {
+    // NOTE: this is for the info dictionary on its own, as it arrives from
+    // peers over the ut_metadata extension, so we don't trust its contents.
+    pub fn from_bytes(info: &[u8]) -> Result<Self, TorrentError> {
+        let info = bencode::parse_with_options(info, DecodeOptions::untrusted())?;
+
+        Self::from_value(&info)
+    }
+
+    fn from_value(info: &ValueRef) -> Result<Self, TorrentError> {
+        if info.as_dict().is_none() {
+            return Err(TorrentError::InvalidField("info".to_string()));
+        }
+
+        let files = match info.get("files") {
+            Some(files) => match files.as_list() {
+                Some(files) => files
+                    .iter()
+                    .enumerate()
+                    .map(|(i, file)| Self::parse_file(i, file))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => return Err(TorrentError::InvalidField("info.files".to_string())),
+            },
+            None => Vec::new(),
+        };
+
+        let meta_version = get_int(info, "meta version", "info.")?;
+
+        let file_tree = match info.get("file tree") {
+            Some(tree) => {
+                let mut file_tree = Vec::new();
+                Self::parse_file_tree(tree, &mut Vec::new(), &mut file_tree)?;
+                file_tree
+            }
+            None if meta_version == Some(2) => {
+                return Err(TorrentError::MissingField("info.file tree".to_string()))
+            }
+            None => Vec::new(),
+        };
+
+        let length = match get_int(info, "length", "info.")? {
+            Some(len) if len >= 0 => len,
+            Some(_) => return Err(TorrentError::InvalidField("info.length".to_string())),
+            None if !files.is_empty() => {
+                total_length(files.iter().map(|file| file.length), "info.files")?
+            }
+            None if !file_tree.is_empty() => {
+                total_length(file_tree.iter().map(|file| file.length), "info.file tree")?
+            }
+            None => return Err(TorrentError::MissingField("info.length".to_string())),
+        };
+
+        let piece_length = match get_int(info, "piece length", "info.")? {
+            Some(len) => len,
+            None => return Err(TorrentError::MissingField("info.piece length".to_string())),
+        };
+
+        // BEP 52 pieces are whole merkle subtrees, so they have to be a power
+        // of two and at least a block.
+        let is_valid_v2_piece_length = piece_length >= merkle::MERKLE_BLOCK_SIZE as i64
+            && (piece_length as u64).is_power_of_two();
+
+        if meta_version == Some(2) && !is_valid_v2_piece_length {
+            return Err(TorrentError::InvalidField("info.piece length".to_string()));
+        }
+
+        // v2-only torrents don't have any SHA-1 piece hashes.
+        let pieces: &[u8] = match info.get("pieces") {
+            Some(pieces) => match pieces.as_bytes() {
+                Some(bytes) => bytes,
+                None => return Err(TorrentError::InvalidField("info.pieces".to_string())),
+            },
+            None if meta_version == Some(2) => &[],
+            None => return Err(TorrentError::MissingField("info.pieces".to_string())),
+        };
+
+        let piece_hashes = pieces.chunks(20).map(const_hex::encode).collect();
+
+        // We hash the info dictionary exactly as it appeared in the input.
+        // Re-encoding it would give a different hash for non-canonical torrents.
+        let hash = InfoHash::of(info.raw());
+        let hash_v2 = (meta_version == Some(2)).then(|| InfoHashV2::of(info.raw()));
+
+        Ok(Self {
+            name: get_string(info, "name", "info.")?,
+            length,
+            files,
+            piece_length,
+            piece_hashes,
+            private: get_int(info, "private", "info.")? == Some(1),
+            source: get_string(info, "source", "info.")?,
+            hash,
+            meta_version,
+            file_tree,
+            hash_v2,
+            raw: info.raw().to_vec(),
+        })
+    }
+
+    // Has SHA-1 piece hashes, so it can be downloaded from a v1 swarm.
+    pub fn is_v1(&self) -> bool {
+        !self.piece_hashes.is_empty() || self.meta_version != Some(2)
+    }
+
+    pub fn is_v2(&self) -> bool {
+        self.meta_version == Some(2)
+    }
+
+    pub fn is_hybrid(&self) -> bool {
+        self.is_v1() && self.is_v2()
+    }
+
+    // The v2 info hash cut down to 20 bytes, which is what trackers and the
+    // peer handshake use.
+    pub fn truncated_hash_v2(&self) -> Option<InfoHash> {
+        self.hash_v2.as_ref().map(InfoHashV2::truncated)
+    }
+
+    // In the file tree, a file is a dictionary with a single empty key, and
+    // everything else is a directory.
+    fn parse_file_tree(
+        node: &ValueRef,
+        path: &mut Vec<String>,
+        files: &mut Vec<FileTreeEntry>,
+    ) -> Result<(), TorrentError> {
+        let invalid = |path: &[String]| {
+            TorrentError::InvalidField(format!("info.file tree.{}", path.join(".")))
+        };
+
+        let entries = node.as_dict().ok_or_else(|| invalid(path))?;
+
+        for (name, child) in entries {
+            if name.is_empty() {
+                let prefix = format!("info.file tree.{}.", path.join("."));
+
+                let length = match get_int(child, "length", &prefix)? {
+                    Some(len) if len >= 0 => len,
+                    Some(_) => return Err(TorrentError::InvalidField(format!("{}length", prefix))),
+                    None => return Err(TorrentError::MissingField(format!("{}length", prefix))),
+                };
+
+                let pieces_root = match child.get("pieces root") {
+                    Some(root) => Some(
+                        root.as_bytes()
+                            .and_then(|root| MerkleHash::try_from(root).ok())
+                            .ok_or_else(|| {
+                                TorrentError::InvalidField(format!("{}pieces root", prefix))
+                            })?,
+                    ),
+                    None => None,
+                };
+
+                files.push(FileTreeEntry {
+                    path: path.clone(),
+                    length,
+                    pieces_root,
+                });
+            } else {
+                path.push(String::from_utf8_lossy(name).to_string());
+                Self::parse_file_tree(child, path, files)?;
+                path.pop();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_file(index: usize, file: &ValueRef) -> Result<TorrentFile, TorrentError> {
+        let prefix = format!("info.files.{}.", index);
+
+        let length = match get_int(file, "length", &prefix)? {
+            Some(len) if len >= 0 => len,
+            Some(_) => return Err(TorrentError::InvalidField(format!("{}length", prefix))),
+            None => return Err(TorrentError::MissingField(format!("{}length", prefix))),
+        };
+
+        let path = match get_path(file, "path", &prefix)? {
+            Some(path) => path,
+            None => return Err(TorrentError::MissingField(format!("{}path", prefix))),
+        };
+
+        let attr = get_string(file, "attr", &prefix)?.unwrap_or_default();
+
+        // NOTE: the target only matters for symlinks, so it's ignored on
+        // anything else rather than rejected.
+        let symlink_path = match attr.contains('l') {
+            true => match get_path(file, "symlink path", &prefix)? {
+                Some(path) => Some(path),
+                None => {
+                    return Err(TorrentError::MissingField(format!(
+                        "{}symlink path",
+                        prefix
+                    )))
+                }
+            },
+            false => None,
+        };
+
+        Ok(TorrentFile {
+            length,
+            path,
+            attr,
+            symlink_path,
+        })
+    }
+}
+
+fn get_string(dict: &ValueRef, key: &str, prefix: &str) -> Result<Option<String>, TorrentError> {
+    match dict.get(key) {
+        Some(value) => match value.as_bytes() {
+            Some(bytes) => Ok(Some(String::from_utf8_lossy(bytes).to_string())),
+            None => Err(TorrentError::InvalidField(format!("{}{}", prefix, key))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn get_path(dict: &ValueRef, key: &str, prefix: &str) -> Result<Option<Vec<String>>, TorrentError> {
+    match dict.get(key) {
+        Some(path) => path
+            .as_list()
+            .and_then(|path| {
+                path.iter()
+                    .map(|component| {
+                        component
+                            .as_bytes()
+                            .map(|component| String::from_utf8_lossy(component).to_string())
+                    })
+                    .collect::<Option<Vec<_>>>()
+            })
+            .map(Some)
+            .ok_or_else(|| TorrentError::InvalidField(format!("{}{}", prefix, key))),
+        None => Ok(None),
+    }
+}
+
+fn get_int(dict: &ValueRef, key: &str, prefix: &str) -> Result<Option<i64>, TorrentError> {
+    match dict.get(key) {
+        Some(value) => match value.as_int() {
+            Some(int) => Ok(Some(int)),
+            None => Err(TorrentError::InvalidField(format!("{}{}", prefix, key))),
+        },
+        None => Ok(None),
+    }
+}
+
+// The combined length of every file, which has to fit in an `i64`.
+fn total_length(mut lengths: impl Iterator<Item = i64>, key: &str) -> Result<i64, TorrentError> {
+    lengths
+        .try_fold(0_i64, |total, length| total.checked_add(length))
+        .ok_or_else(|| TorrentError::InvalidField(key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metainfo_optional_fields() {
+        let input_info =
+            "d6:lengthi420e4:name6:sample12:piece lengthi512e6:pieces20:01234567890123456789\
+            7:privatei1e6:source4:teste";
+        let input = format!(
+            "d8:announce8:fake_url7:comment5:hello10:created by4:test\
+            13:creation datei1700000000e8:encoding5:UTF-84:info{}e",
+            input_info
+        );
+
+        let expected = Metainfo {
+            announce: Some("fake_url".to_string()),
+            announce_list: Vec::new(),
+            comment: Some("hello".to_string()),
+            created_by: Some("test".to_string()),
+            creation_date: Some(1700000000),
+            encoding: Some("UTF-8".to_string()),
+            info: Info {
+                name: Some("sample".to_string()),
+                length: 420,
+                files: Vec::new(),
+                piece_length: 512,
+                piece_hashes: vec!["3031323334353637383930313233343536373839".to_string()],
+                private: true,
+                source: Some("test".to_string()),
+                hash: InfoHash::of(input_info.as_bytes()),
+                meta_version: None,
+                file_tree: Vec::new(),
+                hash_v2: None,
+                raw: input_info.as_bytes().to_vec(),
+            },
+            piece_layers: BTreeMap::new(),
+            extra: BTreeMap::new(),
+        };
+
+        assert_eq!(expected, Metainfo::from_bytes(input.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_metainfo_errors() {
+        let errors = [
+            ("i42e", "torrent is not a bencoded dictionary"),
+            ("d8:announce8:fake_urle", "missing info entry"),
+            (
+                "d4:infod12:piece lengthi512e6:pieces0:ee",
+                "missing info.length entry",
+            ),
+            (
+                "d4:infod6:lengthi1e12:piece lengthi512e6:piecesi0eee",
+                "invalid info.pieces entry",
+            ),
+            (
+                "d7:comment3:abc4:infod5:filesld4:pathl1:aeee12:piece lengthi1e6:pieces0:ee",
+                "missing info.files.0.length entry",
+            ),
+            (
+                "d13:creation date3:now4:infod6:lengthi1e12:piece lengthi1e6:pieces0:ee",
+                "invalid creation date entry",
+            ),
+            (
+                "d4:infod5:filesld4:attr1:l6:lengthi0e4:pathl1:aeee12:piece lengthi1e6:pieces0:ee",
+                "missing info.files.0.symlink path entry",
+            ),
+            (
+                "d4:infod5:filesld6:lengthi9223372036854775807e4:pathl1:aee\
+                d6:lengthi9223372036854775807e4:pathl1:beee12:piece lengthi1e6:pieces0:ee",
+                "invalid info.files entry",
+            ),
+            (
+                "d4:infod5:filesld6:lengthi-1e4:pathl1:aeee12:piece lengthi1e6:pieces0:ee",
+                "invalid info.files.0.length entry",
+            ),
+            (
+                "d4:infod6:lengthi-1e12:piece lengthi1e6:pieces0:ee",
+                "invalid info.length entry",
+            ),
+            (
+                "d4:infod9:file treed1:ad0:d6:lengthi9223372036854775807eee\
+                1:bd0:d6:lengthi9223372036854775807eeee\
+                12:meta versioni2e12:piece lengthi16384eee",
+                "invalid info.file tree entry",
+            ),
+        ];
+
+        for (input, expected) in errors {
+            let actual = Metainfo::from_bytes(input.as_bytes()).unwrap_err();
+
+            assert_eq!(expected, actual.to_string(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_metainfo_file_attributes() {
+        let input = "d4:infod5:filesl\
+            d4:attr1:x6:lengthi1e4:pathl3:rune\
+            e\
+            d4:attr1:p6:lengthi1e4:pathl4:.pad1:1e\
+            e\
+            d4:attr2:hl6:lengthi0e4:pathl4:linke12:symlink pathl3:runee\
+            e4:name1:a12:piece lengthi2e6:pieces20:01234567890123456789ee";
+
+        let files = Metainfo::from_bytes(input.as_bytes()).unwrap().info.files;
+
+        assert!(files[0].is_executable() && !files[0].is_padding());
+        assert!(files[1].is_padding());
+        assert!(files[2].is_symlink() && files[2].is_hidden());
+        assert_eq!(Some(vec!["run".to_string()]), files[2].symlink_path);
+        assert_eq!(None, files[0].symlink_path);
+    }
+
+    #[test]
+    fn test_metainfo_v2() {
+        let data = vec![1_u8; 40_000];
+        let root = merkle::pieces_root(&data).unwrap();
+        let layer = merkle::piece_layer(&data, 16 * 1024);
+
+        let mut input_info = b"d9:file treed5:a.txtd0:d6:lengthi40000e11:pieces root32:".to_vec();
+        input_info.extend_from_slice(&root);
+        input_info.extend_from_slice(b"eee12:meta versioni2e4:name1:a12:piece lengthi16384ee");
+
+        let mut input = b"d4:info".to_vec();
+        input.extend_from_slice(&input_info);
+        input.extend_from_slice(b"12:piece layersd32:");
+        input.extend_from_slice(&root);
+        input.extend_from_slice(format!("{}:", layer.len() * 32).as_bytes());
+        input.extend_from_slice(&layer.concat());
+        input.extend_from_slice(b"ee");
+
+        let metainfo = Metainfo::from_bytes(&input).unwrap();
+
+        assert!(metainfo.info.is_v2());
+        assert!(!metainfo.info.is_hybrid());
+        assert_eq!(40_000, metainfo.info.length);
+        assert_eq!(
+            vec![FileTreeEntry {
+                path: vec!["a.txt".to_string()],
+                length: 40_000,
+                pieces_root: Some(root),
+            }],
+            metainfo.info.file_tree
+        );
+        assert_eq!(Some(InfoHashV2::of(&input_info)), metainfo.info.hash_v2);
+
+        // Without its piece layer, the file can't be verified.
+        let mut missing_layers = b"d4:info".to_vec();
+        missing_layers.extend_from_slice(&input_info);
+        missing_layers.push(b'e');
+
+        assert_eq!(
+            "invalid piece layers.a.txt entry",
+            Metainfo::from_bytes(&missing_layers)
+                .unwrap_err()
+                .to_string()
+        );
+
+        // Pieces smaller than a block would leave nothing to build the tree from.
+        for piece_length in [1, 16_383, 24_576] {
+            let mut bad_piece_length =
+                b"d4:infod9:file treed5:a.txtd0:d6:lengthi40000e11:pieces root32:".to_vec();
+            bad_piece_length.extend_from_slice(&root);
+            bad_piece_length.extend_from_slice(
+                format!(
+                    "eee12:meta versioni2e4:name1:a12:piece lengthi{}eee",
+                    piece_length
+                )
+                .as_bytes(),
+            );
+
+            assert_eq!(
+                "invalid info.piece length entry",
+                Metainfo::from_bytes(&bad_piece_length)
+                    .unwrap_err()
+                    .to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_metainfo_from_missing_file() {
+        let actual = Metainfo::from_file("this/file/does/not/exist.torrent");
+
+        assert!(matches!(actual, Err(TorrentError::Io(_))));
+    }
+}
@@ -0,0 +1,331 @@
+use super::Metainfo;
+use crate::bencode::{self, DecodeOptions};
+use std::{collections::HashSet, fmt};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    // Worth knowing about, but clients will still cope with it.
+    Warning,
+    // The torrent is broken, or unsafe to download.
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        self.issues.push(ValidationIssue {
+            severity: Severity::Error,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, message: impl Into<String>) {
+        self.issues.push(ValidationIssue {
+            severity: Severity::Warning,
+            message: message.into(),
+        });
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+// Checks a .torrent file for structural problems, meant for torrents we
+// didn't create ourselves, so it's parsed with the untrusted limits.
+pub fn validate_torrent(contents: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let metainfo = match Metainfo::from_bytes_with_options(contents, DecodeOptions::untrusted()) {
+        Ok(metainfo) => metainfo,
+        Err(err) => {
+            report.error(err.to_string());
+            return report;
+        }
+    };
+
+    let strict = DecodeOptions {
+        strict: true,
+        ..DecodeOptions::untrusted()
+    };
+
+    if let Err(err) = bencode::decode_with_options(contents, strict) {
+        report.warning(format!("not canonical bencode: {}", err));
+    }
+
+    check_trackers(&metainfo, &mut report);
+    check_pieces(&metainfo, &mut report);
+    check_files(&metainfo, &mut report);
+
+    report
+}
+
+fn check_trackers(metainfo: &Metainfo, report: &mut ValidationReport) {
+    match (&metainfo.announce, metainfo.announce_list.is_empty()) {
+        (Some(url), _) if url.trim().is_empty() => report.warning("announce URL is empty"),
+        (None, true) => report.warning("no trackers, peers can only be found through DHT"),
+        _ => {}
+    }
+
+    let empty_urls = metainfo
+        .announce_list
+        .iter()
+        .flatten()
+        .filter(|url| url.trim().is_empty())
+        .count();
+
+    if empty_urls > 0 {
+        report.warning(format!("announce-list has {} empty URLs", empty_urls));
+    }
+}
+
+fn check_pieces(metainfo: &Metainfo, report: &mut ValidationReport) {
+    let info = &metainfo.info;
+
+    if info.piece_length <= 0 {
+        report.error(format!(
+            "piece length must be positive, got {}",
+            info.piece_length
+        ));
+        return;
+    }
+
+    if !(info.piece_length as u64).is_power_of_two() {
+        let message = format!("piece length {} is not a power of two", info.piece_length);
+
+        // BEP 52 requires it, older clients just assume it.
+        match info.is_v2() {
+            true => report.error(message),
+            false => report.warning(message),
+        }
+    }
+
+    if !info.is_v1() {
+        return;
+    }
+
+    // Each hash is 40 hex characters, so a short one means `pieces` was cut off.
+    if info
+        .piece_hashes
+        .last()
+        .is_some_and(|hash| hash.len() != 40)
+    {
+        report.error("pieces is not a multiple of 20 bytes");
+    }
+
+    if info.length >= 0 {
+        let expected = (info.length as u64).div_ceil(info.piece_length as u64);
+
+        if expected != info.piece_hashes.len() as u64 {
+            report.error(format!(
+                "expected {} pieces for {} bytes with {} byte pieces, found {}",
+                expected,
+                info.length,
+                info.piece_length,
+                info.piece_hashes.len()
+            ));
+        }
+    }
+}
+
+fn check_files(metainfo: &Metainfo, report: &mut ValidationReport) {
+    let info = &metainfo.info;
+
+    match &info.name {
+        Some(name) if !is_safe_component(name) => {
+            report.error(format!("name {:?} is not a safe file name", name))
+        }
+        None => report.warning("no name, so one has to be made up"),
+        _ => {}
+    }
+
+    check_paths(info.files.iter().map(|file| &file.path), report);
+    check_paths(info.file_tree.iter().map(|file| &file.path), report);
+}
+
+fn check_paths<'a>(paths: impl Iterator<Item = &'a Vec<String>>, report: &mut ValidationReport) {
+    let mut seen = HashSet::new();
+
+    for path in paths {
+        let display = path.join("/");
+
+        if path.is_empty() {
+            report.error("a file has an empty path");
+        } else if let Some(component) = path.iter().find(|c| !is_safe_component(c)) {
+            report.error(format!(
+                "{} has an unsafe path component {:?}",
+                display, component
+            ));
+        }
+
+        // NOTE: BEP 47 padding files are named after their length, so they
+        // can legitimately share a path.
+        let is_padding = path.first().is_some_and(|c| c == ".pad");
+
+        if !seen.insert(path) && !is_padding {
+            report.error(format!("{} appears more than once", display));
+        }
+    }
+}
+
+// Anything that could escape the download directory, or that some
+// filesystems can't store.
+fn is_safe_component(component: &str) -> bool {
+    !component.is_empty()
+        && component != "."
+        && component != ".."
+        && !component.contains(['/', '\\'])
+        && !component.chars().any(char::is_control)
+        && component.as_bytes().get(1) != Some(&b':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(report: &ValidationReport) -> Vec<String> {
+        report.issues.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_validate_valid_torrent() {
+        let input = "d8:announce8:fake_url4:infod6:lengthi420e4:name6:sample\
+            12:piece lengthi512e6:pieces20:01234567890123456789ee";
+
+        let report = validate_torrent(input.as_bytes());
+
+        assert!(report.is_valid());
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn test_validate_pieces() {
+        let input = "d8:announce0:4:infod6:lengthi2000e4:name6:sample\
+            12:piece lengthi500e6:pieces25:0123456789012345678901234ee";
+
+        let report = validate_torrent(input.as_bytes());
+
+        assert!(!report.is_valid());
+        assert_eq!(
+            vec![
+                "warning: announce URL is empty",
+                "warning: piece length 500 is not a power of two",
+                "error: pieces is not a multiple of 20 bytes",
+                "error: expected 4 pieces for 2000 bytes with 500 byte pieces, found 2",
+            ],
+            messages(&report)
+        );
+    }
+
+    #[test]
+    fn test_validate_files() {
+        let input = "d8:announce8:fake_url4:infod5:filesl\
+            d6:lengthi0e4:pathl2:..6:passwdee\
+            d6:lengthi0e4:pathl1:aee\
+            d6:lengthi0e4:pathl1:aee\
+            e4:name6:sample12:piece lengthi512e6:pieces0:ee";
+
+        let report = validate_torrent(input.as_bytes());
+
+        assert_eq!(
+            vec![
+                "error: ../passwd has an unsafe path component \"..\"",
+                "error: a appears more than once",
+            ],
+            messages(&report)
+        );
+    }
+
+    #[test]
+    fn test_validate_negative_length() {
+        let input = "d8:announce8:fake_url4:infod5:filesl\
+            d6:lengthi-5e4:pathl6:passwdee\
+            e4:name6:sample12:piece lengthi512e6:pieces0:ee";
+
+        let report = validate_torrent(input.as_bytes());
+
+        assert_eq!(
+            vec!["error: invalid info.files.0.length entry"],
+            messages(&report)
+        );
+    }
+
+    #[test]
+    fn test_validate_malformed_sizes() {
+        let mut v2_tiny_pieces =
+            b"d4:infod9:file treed5:a.txtd0:d6:lengthi40000e11:pieces root32:".to_vec();
+        v2_tiny_pieces.extend_from_slice(&[0; 32]);
+        v2_tiny_pieces.extend_from_slice(b"eee12:meta versioni2e4:name1:a12:piece lengthi1eee");
+
+        let overflowing_files = b"d4:infod5:filesl\
+            d6:lengthi9223372036854775807e4:pathl1:aee\
+            d6:lengthi9223372036854775807e4:pathl1:bee\
+            e4:name1:a12:piece lengthi1e6:pieces0:ee";
+
+        assert_eq!(
+            vec!["error: invalid info.piece length entry"],
+            messages(&validate_torrent(&v2_tiny_pieces))
+        );
+        assert_eq!(
+            vec!["error: invalid info.files entry"],
+            messages(&validate_torrent(overflowing_files))
+        );
+    }
+
+    #[test]
+    fn test_validate_uses_untrusted_limits() {
+        let nested = format!("d4:infol{}{}ee", "l".repeat(100), "e".repeat(100));
+
+        let report = validate_torrent(nested.as_bytes());
+
+        assert_eq!(
+            vec!["error: invalid bencode: nested more than 32 levels deep at byte 38"],
+            messages(&report)
+        );
+    }
+
+    #[test]
+    fn test_validate_non_canonical() {
+        let input = "d4:infod12:piece lengthi512e6:lengthi0e4:name1:a6:pieces0:e\
+            8:announce8:fake_urle";
+
+        let report = validate_torrent(input.as_bytes());
+
+        assert!(report.is_valid());
+        assert_eq!(1, report.issues.len());
+        assert!(messages(&report)[0].starts_with("warning: not canonical bencode"));
+    }
+
+    #[test]
+    fn test_validate_unparseable() {
+        let report = validate_torrent(b"not a torrent");
+
+        assert!(!report.is_valid());
+        assert_eq!(1, report.issues.len());
+    }
+}